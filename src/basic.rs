@@ -1,9 +1,15 @@
+use super::clock;
+use super::profile;
 use super::state::State;
 use super::sys;
-use dirs;
+use super::util::is_var_set;
 use osyris::bstring::BString;
 use osyris::eval::{FuncResult, Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
 use std::env;
+use std::fs;
+use std::path::Path;
 use std::rc::Rc;
 
 fn username(_: Vec<ValRef>, scope: Scope) -> FuncResult {
@@ -13,46 +19,67 @@ fn username(_: Vec<ValRef>, scope: Scope) -> FuncResult {
     ))
 }
 
-fn host(_: Vec<ValRef>, scope: Scope) -> FuncResult {
-    Ok((
-        ValRef::String(Rc::new(BString::from_string(sys::hostname()))),
-        scope,
-    ))
+fn in_container(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    // systemd-nspawn and podman set $container, podman also creates
+    // /run/.containerenv, and docker creates /.dockerenv
+    let in_container = is_var_set("container")
+        || Path::new("/run/.containerenv").exists()
+        || Path::new("/.dockerenv").exists();
+    Ok((ValRef::Bool(in_container), scope))
 }
 
-fn login_name(_: Vec<ValRef>, scope: Scope) -> FuncResult {
-    Ok((
-        ValRef::String(Rc::new(BString::from_string(sys::login_name()))),
-        scope,
-    ))
-}
+fn container_name(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    // /run/.containerenv contains lines like 'name="fedora-toolbox-36"'
+    if let Ok(content) = fs::read_to_string("/run/.containerenv") {
+        for line in content.lines() {
+            if let Some(name) = line.strip_prefix("name=") {
+                let name = name.trim_matches('"');
+                if !name.is_empty() {
+                    return Ok((ValRef::String(Rc::new(BString::from_str(name))), scope));
+                }
+            }
+        }
+    }
 
-fn is_remote(_: Vec<ValRef>, scope: Scope) -> FuncResult {
-    match env::var("SSH_CLIENT") {
-        Ok(_) => Ok((ValRef::Bool(true), scope)),
-        Err(_) => Ok((ValRef::Bool(false), scope)),
+    // distrobox sets $CONTAINER_ID to the container's name
+    match env::var_os("CONTAINER_ID") {
+        Some(name) if !name.is_empty() => {
+            Ok((ValRef::String(Rc::new(BString::from_os_str(&name))), scope))
+        }
+        _ => Ok((ValRef::None, scope)),
     }
 }
 
-fn replace_home_path(path: BString) -> BString {
-    let home = match dirs::home_dir() {
-        Some(home) => BString::from_os_str(home.as_os_str()),
-        None => return path,
-    };
+fn in_toolbox(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((ValRef::Bool(Path::new("/run/.toolboxenv").exists()), scope))
+}
 
-    match path.strip_prefix(&home) {
-        Some(stripped) => BString::from_string(format!("~{}", String::from_utf8_lossy(stripped))),
-        None => path,
-    }
+fn in_nix_shell(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((ValRef::Bool(is_var_set("IN_NIX_SHELL")), scope))
 }
 
-fn cwd(_: Vec<ValRef>, scope: Scope) -> FuncResult {
-    let wd = match env::current_dir() {
-        Ok(wd) => replace_home_path(BString::from_os_str(wd.as_os_str())),
-        Err(..) => BString::from_str(""),
-    };
+fn nix_shell_name(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    // nix-shell exports the derivation's attributes, including its 'name'
+    if !is_var_set("IN_NIX_SHELL") {
+        return Ok((ValRef::None, scope));
+    }
 
-    Ok((ValRef::String(Rc::new(replace_home_path(wd))), scope))
+    match env::var_os("name") {
+        Some(name) if !name.is_empty() => {
+            Ok((ValRef::String(Rc::new(BString::from_os_str(&name))), scope))
+        }
+        _ => Ok((ValRef::None, scope)),
+    }
+}
+
+fn shell_level(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    match env::var("SHLVL")
+        .ok()
+        .and_then(|l| str::parse::<u32>(l.trim()).ok())
+    {
+        Some(level) => Ok((ValRef::Number(level as f64), scope)),
+        None => Ok((ValRef::None, scope)),
+    }
 }
 
 fn term_width(_: Vec<ValRef>, scope: Scope) -> FuncResult {
@@ -65,39 +92,148 @@ fn term_height(_: Vec<ValRef>, stack: Scope) -> FuncResult {
     Ok((ValRef::Number(h as f64), stack))
 }
 
-fn getenv(args: Vec<ValRef>, stack: Scope) -> FuncResult {
-    if args.len() != 1 {
-        return Err(StackTrace::from_str("'getenv' requires 1 argument"));
+// (getenv key [default])
+// Returns the default, or none, if the variable isn't set.
+// Values which aren't valid UTF-8 are returned byte for byte.
+fn getenv(mut args: Vec<ValRef>, stack: Scope) -> FuncResult {
+    if args.is_empty() || args.len() > 2 {
+        return Err(StackTrace::from_str("'getenv' requires 1 or 2 arguments"));
     }
 
+    let default = match args.len() {
+        2 => args.pop().unwrap(),
+        _ => ValRef::None,
+    };
+
     let key = match &args[0] {
         ValRef::String(s) => s,
         _ => return Err(StackTrace::from_str("'getenv' requires a string argument")),
     };
 
-    let val = match env::var(key.to_os_str()) {
-        Ok(val) => BString::from_string(val),
-        Err(err) => {
+    match env::var_os(key.to_os_str()) {
+        Some(val) => Ok((ValRef::String(Rc::new(BString::from_os_str(&val))), stack)),
+        None => Ok((default, stack)),
+    }
+}
+
+fn env_is_set(args: Vec<ValRef>, stack: Scope) -> FuncResult {
+    if args.len() != 1 {
+        return Err(StackTrace::from_str("'env-set?' requires 1 argument"));
+    }
+
+    let key = match &args[0] {
+        ValRef::String(s) => s,
+        _ => {
+            return Err(StackTrace::from_str(
+                "'env-set?' requires a string argument",
+            ))
+        }
+    };
+
+    Ok((ValRef::Bool(env::var_os(key.to_os_str()).is_some()), stack))
+}
+
+fn env_dict(_: Vec<ValRef>, stack: Scope) -> FuncResult {
+    let mut dict = HashMap::new();
+    for (key, val) in env::vars_os() {
+        dict.insert(
+            BString::from_os_str(&key),
+            ValRef::String(Rc::new(BString::from_os_str(&val))),
+        );
+    }
+
+    Ok((ValRef::Dict(Rc::new(RefCell::new(dict))), stack))
+}
+
+fn now(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((ValRef::Number(clock::now() as f64), scope))
+}
+
+fn strftime_args(name: &str, args: &[ValRef]) -> Result<(String, i64), StackTrace> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(StackTrace::from_string(format!(
+            "'{}' requires 1 or 2 arguments",
+            name
+        )));
+    }
+
+    let fmt = match &args[0] {
+        ValRef::String(s) => String::from_utf8_lossy(s.as_bytes()).to_string(),
+        _ => {
             return Err(StackTrace::from_string(format!(
-                "'getenv' failed with key '{}': {}",
-                key, err
+                "'{}' requires a string as its first argument",
+                name
             )))
         }
     };
 
-    Ok((ValRef::String(Rc::new(val)), stack))
+    let ts = match args.get(1) {
+        None => clock::now(),
+        Some(ValRef::Number(ts)) => *ts as i64,
+        Some(..) => {
+            return Err(StackTrace::from_string(format!(
+                "'{}' requires a number as its second argument",
+                name
+            )))
+        }
+    };
+
+    Ok((fmt, ts))
+}
+
+fn strftime(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let (fmt, ts) = strftime_args("strftime", &args)?;
+    let s = clock::strftime(&fmt, &clock::local_time(ts));
+    Ok((ValRef::String(Rc::new(BString::from_string(s))), scope))
+}
+
+fn strftime_utc(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let (fmt, ts) = strftime_args("strftime-utc", &args)?;
+    let s = clock::strftime(&fmt, &clock::utc_time(ts));
+    Ok((ValRef::String(Rc::new(BString::from_string(s))), scope))
 }
 
 pub fn init(mut s: Scope, state: &Rc<State>) -> Scope {
     s = s.put("exit-code", ValRef::Number(state.exit_code as f64));
     s = s.put("space", ValRef::String(Rc::new(BString::from_str(" "))));
-    s = s.put_lazy("username", Rc::new(username));
-    s = s.put_lazy("host", Rc::new(host));
-    s = s.put_lazy("login-name", Rc::new(login_name));
-    s = s.put_lazy("is-remote?", Rc::new(is_remote));
-    s = s.put_lazy("cwd", Rc::new(cwd));
-    s = s.put_lazy("term-width", Rc::new(term_width));
-    s = s.put_lazy("term-height", Rc::new(term_height));
+    s = s.put_lazy("username", profile::wrap("username", Rc::new(username)));
+    s = s.put_lazy(
+        "in-container?",
+        profile::wrap("in-container?", Rc::new(in_container)),
+    );
+    s = s.put_lazy(
+        "container-name",
+        profile::wrap("container-name", Rc::new(container_name)),
+    );
+    s = s.put_lazy(
+        "in-toolbox?",
+        profile::wrap("in-toolbox?", Rc::new(in_toolbox)),
+    );
+    s = s.put_lazy(
+        "in-nix-shell?",
+        profile::wrap("in-nix-shell?", Rc::new(in_nix_shell)),
+    );
+    s = s.put_lazy(
+        "nix-shell-name",
+        profile::wrap("nix-shell-name", Rc::new(nix_shell_name)),
+    );
+    s = s.put_lazy(
+        "shell-level",
+        profile::wrap("shell-level", Rc::new(shell_level)),
+    );
+    s = s.put_lazy(
+        "term-width",
+        profile::wrap("term-width", Rc::new(term_width)),
+    );
+    s = s.put_lazy(
+        "term-height",
+        profile::wrap("term-height", Rc::new(term_height)),
+    );
+    s = s.put_lazy("now", profile::wrap("now", Rc::new(now)));
+    s = s.put_lazy("env", profile::wrap("env", Rc::new(env_dict)));
     s = s.put_func("getenv", Rc::new(getenv));
+    s = s.put_func("env-set?", Rc::new(env_is_set));
+    s = s.put_func("strftime", Rc::new(strftime));
+    s = s.put_func("strftime-utc", Rc::new(strftime_utc));
     s
 }
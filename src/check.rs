@@ -0,0 +1,204 @@
+use super::import;
+use super::source::{self, Token, TokenKind};
+use super::{print_config_ps1, Printer};
+use osyris::bstring::BString;
+use osyris::eval::{self, Scope, ValRef};
+use osyris::parse;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::rc::Rc;
+
+fn empty_string() -> ValRef {
+    ValRef::String(Rc::new(BString::from_str("")))
+}
+
+// Nothing is run while checking; 'exec' produces no output,
+// and 'read' passes strings through so that '(read (exec ...))' works.
+// Nothing is prefetched, and files can't be opened, created or written.
+fn mock_io(mut scope: Scope) -> Scope {
+    scope = scope.put_func("exec", Rc::new(|_, scope| Ok((empty_string(), scope))));
+    scope = scope.put_func(
+        "read",
+        Rc::new(|args, scope| match args.first() {
+            Some(ValRef::String(s)) => Ok((ValRef::String(s.clone()), scope)),
+            _ => Ok((empty_string(), scope)),
+        }),
+    );
+
+    for name in [
+        "prefetch",
+        "prefetch-exec",
+        "open",
+        "create",
+        "write",
+        "seek",
+        "close",
+    ] {
+        if scope.lookup(&BString::from_str(name)).is_some() {
+            scope = scope.put_func(name, Rc::new(|_, scope| Ok((ValRef::None, scope))));
+        }
+    }
+
+    scope
+}
+
+// The name an identifier token refers to; 'a.b' looks up 'a'
+fn ident_name<'a>(tok: &Token<'a>) -> &'a str {
+    match tok.text.split_once('.') {
+        Some((ident, _)) if !ident.is_empty() => ident,
+        _ => tok.text,
+    }
+}
+
+// Lazies do real work when they're evaluated, like reading git's files
+// or running the project file, so while checking, every lazy which a file
+// refers to is replaced with one which returns none before the file runs
+fn stub_lazies(tokens: &[Token], mut scope: Scope) -> Scope {
+    for tok in tokens.iter().filter(|tok| tok.kind == TokenKind::Ident) {
+        let name = ident_name(tok);
+        if let Some(ValRef::Lazy(..) | ValRef::ProtectedLazy(..)) =
+            scope.lookup(&BString::from_str(name))
+        {
+            scope = scope.put_lazy(name, Rc::new(|_, scope| Ok((ValRef::None, scope))));
+        }
+    }
+
+    scope
+}
+
+// Files run by 'import' and 'include' get their lazies stubbed too,
+// so they can still be read and checked, while everything they could run is mocked
+fn stub_file_lazies(src: &str, scope: Scope) -> Scope {
+    stub_lazies(&source::scan(src), scope)
+}
+
+// Names are bound by quoting them, as in (def 'x 10) or (lambda 'x {...}),
+// and function parameters aren't in the top-level scope, so quoted names
+// in those places count as defined. Other quoted names are just strings,
+// like the 'git in (prefetch 'git), and don't define anything.
+fn bound_names<'a>(tokens: &[Token<'a>]) -> HashSet<&'a str> {
+    let mut names = HashSet::new();
+    for (i, pair) in tokens.windows(2).enumerate() {
+        if pair[0].text != "(" || pair[1].kind != TokenKind::Ident {
+            continue;
+        }
+
+        let quoted = tokens[i + 2..]
+            .iter()
+            .take_while(|tok| tok.kind == TokenKind::Quote)
+            .map(|tok| &tok.text[1..]);
+        match pair[1].text {
+            "def" | "set" => names.extend(quoted.take(1)),
+            "defn" | "func" | "lambda" => names.extend(quoted),
+            _ => (),
+        }
+    }
+
+    names
+}
+
+// Runs each top-level form of the config on its own, so that an error
+// in one form doesn't hide errors in the next, then renders the prompt
+// the config returns without printing it, and then checks that every
+// identifier in the file refers to something, without evaluating it.
+// Errors are printed with the line they refer to. Returns the error count.
+pub fn check(name: &str, src: &str, config_path: Option<&Path>, mut scope: Scope) -> usize {
+    scope = import::init_with(scope, config_path, Some(stub_file_lazies));
+    let tokens = source::scan(src);
+    scope = stub_lazies(&tokens, mock_io(scope));
+    let mut errors = 0;
+    let mut retval = ValRef::None;
+
+    for form in source::forms(&tokens) {
+        // Only the value of the last form is the prompt,
+        // and only if that form didn't fail
+        retval = ValRef::None;
+        let text = source::form_text(src, &form);
+        let mut reader = parse::Reader::new(text.as_bytes(), BString::from_str(name));
+        let expr = match parse::parse(&mut reader) {
+            Ok(Some(expr)) => expr,
+            Ok(None) => continue,
+            Err(err) => {
+                let msg = format!("Parse error: {}", err.msg);
+                let (line, col) = (err.line as usize, err.col as usize);
+                eprintln!("{}", source::format_error(name, src, line, col, &msg));
+                errors += 1;
+                continue;
+            }
+        };
+
+        match eval::eval(&expr, scope.clone()) {
+            Ok((val, s)) => (retval, scope) = (val, s),
+            Err(err) => {
+                let msg = format!("Error: {}", err);
+                let (line, col) = (form.start.line, form.start.col);
+                eprintln!("{}", source::format_error(name, src, line, col, &msg));
+                errors += 1;
+            }
+        }
+    }
+
+    // The prompt block's elements are only evaluated when it's printed
+    if let Some(last_form) = source::forms(&tokens).pop() {
+        let elements = source::block_elements(&tokens, &last_form);
+        let printer = Rc::new(RefCell::new(Printer {
+            column: 1,
+            row: 1,
+            output: String::new(),
+        }));
+        let res = print_config_ps1(&printer, src, retval, scope.clone(), &last_form, &elements);
+        if let Err((pos, err)) = res {
+            let msg = format!("Error: {}", err);
+            let (line, col) = (pos.line, pos.col);
+            eprintln!("{}", source::format_error(name, src, line, col, &msg));
+            errors += 1;
+        }
+    }
+
+    let bound = bound_names(&tokens);
+    let mut defined: HashMap<&str, bool> = HashMap::new();
+    for tok in tokens.iter().filter(|tok| tok.kind == TokenKind::Ident) {
+        let ident = ident_name(tok);
+        if bound.contains(ident) {
+            continue;
+        }
+
+        let is_defined = *defined
+            .entry(ident)
+            .or_insert_with(|| scope.lookup(&BString::from_str(ident)).is_some());
+        if !is_defined {
+            let msg = format!("'{}': Undefined identifier", ident);
+            let (line, col) = (tok.pos.line, tok.pos.col);
+            eprintln!("{}", source::format_error(name, src, line, col, &msg));
+            errors += 1;
+        }
+    }
+
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bound(src: &str) -> Vec<&str> {
+        let mut names: Vec<&str> = bound_names(&source::scan(src)).into_iter().collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn binding_forms() {
+        assert_eq!(bound("(def 'x 10) (set 'y 'z)"), ["x", "y"]);
+        assert_eq!(bound("(defn 'f 'a 'b {a})"), ["a", "b", "f"]);
+        assert_eq!(bound("(lambda 'a {a})"), ["a"]);
+    }
+
+    #[test]
+    fn other_quotes_bind_nothing() {
+        // A quoted typo mustn't hide the same typo used as an identifier
+        assert!(bound("(prefetch 'git-brnach) git-brnach").is_empty());
+        assert!(bound("'x (list 'def 'y)").is_empty());
+    }
+}
@@ -0,0 +1,722 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+const MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+// A point in time, broken down in some time zone
+pub struct Tm {
+    pub ts: i64,
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub min: u32,
+    pub sec: u32,
+    pub wday: u32,
+    pub yday: u32,
+    pub utoff: i64,
+    pub zone: String,
+}
+
+pub fn now() -> i64 {
+    match SystemTime::now().duration_since(UNIX_EPOCH) {
+        Ok(d) => d.as_secs() as i64,
+        Err(..) => 0,
+    }
+}
+
+// Howard Hinnant's days_from_civil and civil_from_days
+fn days_from_civil(y: i64, m: u32, d: u32) -> i64 {
+    let y = if m <= 2 { y - 1 } else { y };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let m = m as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + d as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+fn civil_from_days(z: i64) -> (i64, u32, u32) {
+    let z = z + 719468;
+    let era = if z >= 0 { z } else { z - 146096 } / 146097;
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let y = yoe + era * 400;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let d = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let m = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    (if m <= 2 { y + 1 } else { y }, m, d)
+}
+
+fn is_leap(y: i64) -> bool {
+    y % 4 == 0 && (y % 100 != 0 || y % 400 == 0)
+}
+
+// 1970-01-01 was a Thursday
+fn weekday(days: i64) -> u32 {
+    (days + 4).rem_euclid(7) as u32
+}
+
+// Timestamps are clamped to about a billion years either way,
+// which keeps all of the date arithmetic from overflowing
+const MAX_TS: i64 = 1 << 55;
+
+fn break_down(ts: i64, utoff: i64, zone: String) -> Tm {
+    let local = ts + utoff;
+    let days = local.div_euclid(86400);
+    let secs = local.rem_euclid(86400) as u32;
+    let (year, month, day) = civil_from_days(days);
+    Tm {
+        ts,
+        year,
+        month,
+        day,
+        hour: secs / 3600,
+        min: secs / 60 % 60,
+        sec: secs % 60,
+        wday: weekday(days),
+        yday: (days - days_from_civil(year, 1, 1)) as u32,
+        utoff,
+        zone,
+    }
+}
+
+#[derive(Clone)]
+struct LocalType {
+    utoff: i64,
+    abbrev: String,
+}
+
+enum RuleDate {
+    // Jn: 1-based day of the year, never counting February 29
+    Julian(u32),
+    // n: 0-based day of the year
+    Day(u32),
+    // Mm.w.d: day d (0 is Sunday) of week w (5 is the last) of month m
+    MonthWeekDay(u32, u32, u32),
+}
+
+struct Rule {
+    date: RuleDate,
+    time: i64,
+}
+
+impl Rule {
+    fn days(&self, year: i64) -> i64 {
+        let jan1 = days_from_civil(year, 1, 1);
+        match self.date {
+            RuleDate::Julian(n) => {
+                let day = n.max(1) as i64 - 1;
+                if is_leap(year) && day >= 59 {
+                    jan1 + day + 1
+                } else {
+                    jan1 + day
+                }
+            }
+            RuleDate::Day(n) => jan1 + n as i64,
+            RuleDate::MonthWeekDay(m, w, d) => {
+                let first = days_from_civil(year, m, 1);
+                let next = match m {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    _ => days_from_civil(year, m + 1, 1),
+                };
+                let mut day = first + (d as i64 - weekday(first) as i64).rem_euclid(7);
+                day += (w.max(1) as i64 - 1) * 7;
+                while day >= next {
+                    day -= 7;
+                }
+                day
+            }
+        }
+    }
+}
+
+// A POSIX TZ string, like 'CET-1CEST,M3.5.0,M10.5.0/3'.
+// TZif files end with one of these to describe times after the last transition.
+struct PosixTz {
+    std: LocalType,
+    dst: Option<(LocalType, Rule, Rule)>,
+}
+
+impl PosixTz {
+    fn lookup(&self, ts: i64) -> LocalType {
+        let (dst, start, end) = match &self.dst {
+            Some(dst) => dst,
+            None => return self.std.clone(),
+        };
+
+        let (year, _, _) = civil_from_days((ts + self.std.utoff).div_euclid(86400));
+
+        // The start is given in standard time, the end in daylight saving time
+        let start_ts = start.days(year) * 86400 + start.time - self.std.utoff;
+        let end_ts = end.days(year) * 86400 + end.time - dst.utoff;
+        let in_dst = if start_ts < end_ts {
+            ts >= start_ts && ts < end_ts
+        } else {
+            // Southern hemisphere
+            ts < end_ts || ts >= start_ts
+        };
+
+        if in_dst {
+            dst.clone()
+        } else {
+            self.std.clone()
+        }
+    }
+}
+
+struct PosixParser<'a> {
+    s: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> PosixParser<'a> {
+    fn peek(&self) -> Option<u8> {
+        self.s.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, ch: u8) -> bool {
+        if self.peek() == Some(ch) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn name(&mut self) -> Option<String> {
+        let start = self.pos;
+        if self.eat(b'<') {
+            while self.peek()? != b'>' {
+                self.pos += 1;
+            }
+            self.pos += 1;
+            return Some(String::from_utf8_lossy(&self.s[start + 1..self.pos - 1]).to_string());
+        }
+
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_alphabetic()) {
+            self.pos += 1;
+        }
+
+        if self.pos - start < 3 {
+            return None;
+        }
+        Some(String::from_utf8_lossy(&self.s[start..self.pos]).to_string())
+    }
+
+    fn number(&mut self) -> Option<i64> {
+        let start = self.pos;
+        while matches!(self.peek(), Some(ch) if ch.is_ascii_digit()) {
+            self.pos += 1;
+        }
+
+        std::str::from_utf8(&self.s[start..self.pos])
+            .ok()
+            .and_then(|n| str::parse::<i64>(n).ok())
+    }
+
+    // [+-]hh[:mm[:ss]], where hh is at most 167
+    fn time(&mut self) -> Option<i64> {
+        let sign = if self.eat(b'-') {
+            -1
+        } else {
+            self.eat(b'+');
+            1
+        };
+
+        let mut secs = self.number().filter(|h| *h <= 167)? * 3600;
+        if self.eat(b':') {
+            secs += self.number().filter(|m| *m <= 59)? * 60;
+            if self.eat(b':') {
+                secs += self.number().filter(|s| *s <= 59)?;
+            }
+        }
+
+        Some(sign * secs)
+    }
+
+    fn rule(&mut self) -> Option<Rule> {
+        let date = if self.eat(b'J') {
+            RuleDate::Julian(self.number().filter(|n| (1..=365).contains(n))? as u32)
+        } else if self.eat(b'M') {
+            let m = self.number().filter(|m| (1..=12).contains(m))?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let w = self.number().filter(|w| (1..=5).contains(w))?;
+            if !self.eat(b'.') {
+                return None;
+            }
+            let d = self.number().filter(|d| (0..=6).contains(d))?;
+            RuleDate::MonthWeekDay(m as u32, w as u32, d as u32)
+        } else {
+            RuleDate::Day(self.number().filter(|n| (0..=365).contains(n))? as u32)
+        };
+
+        let time = if self.eat(b'/') { self.time()? } else { 7200 };
+        Some(Rule { date, time })
+    }
+
+    fn parse(&mut self) -> Option<PosixTz> {
+        // POSIX offsets are the time to add to get to UTC,
+        // so 'CET-1' means UTC+1
+        let std = LocalType {
+            abbrev: self.name()?,
+            utoff: -self.time()?,
+        };
+
+        if self.peek().is_none() {
+            return Some(PosixTz { std, dst: None });
+        }
+
+        let abbrev = self.name()?;
+        let utoff = match self.peek() {
+            Some(b',') | None => std.utoff + 3600,
+            _ => -self.time()?,
+        };
+
+        // Without rules, POSIX leaves it up to the implementation;
+        // everyone uses the US rules
+        let (start, end) = if self.eat(b',') {
+            let start = self.rule()?;
+            if !self.eat(b',') {
+                return None;
+            }
+            (start, self.rule()?)
+        } else {
+            (
+                Rule {
+                    date: RuleDate::MonthWeekDay(3, 2, 0),
+                    time: 7200,
+                },
+                Rule {
+                    date: RuleDate::MonthWeekDay(11, 1, 0),
+                    time: 7200,
+                },
+            )
+        };
+
+        Some(PosixTz {
+            std,
+            dst: Some((LocalType { abbrev, utoff }, start, end)),
+        })
+    }
+}
+
+fn parse_posix_tz(s: &str) -> Option<PosixTz> {
+    PosixParser {
+        s: s.as_bytes(),
+        pos: 0,
+    }
+    .parse()
+}
+
+struct Zone {
+    transitions: Vec<(i64, usize)>,
+    types: Vec<LocalType>,
+    footer: Option<PosixTz>,
+}
+
+impl Zone {
+    fn utc() -> Self {
+        Self {
+            transitions: Vec::new(),
+            types: vec![LocalType {
+                utoff: 0,
+                abbrev: "UTC".to_string(),
+            }],
+            footer: None,
+        }
+    }
+
+    fn lookup(&self, ts: i64) -> LocalType {
+        let count = self.transitions.partition_point(|(t, _)| *t <= ts);
+        if count == self.transitions.len() {
+            if let Some(footer) = &self.footer {
+                return footer.lookup(ts);
+            }
+        }
+
+        // Times before the first transition use the first type
+        let idx = match count {
+            0 => 0,
+            _ => self.transitions[count - 1].1,
+        };
+
+        match self.types.get(idx) {
+            Some(t) => t.clone(),
+            None => LocalType {
+                utoff: 0,
+                abbrev: "UTC".to_string(),
+            },
+        }
+    }
+}
+
+fn read_u32(data: &[u8], pos: usize) -> Option<u32> {
+    let bytes = data.get(pos..pos.checked_add(4)?)?;
+    Some(u32::from_be_bytes(bytes.try_into().ok()?))
+}
+
+fn read_be(data: &[u8], pos: usize, size: usize) -> Option<i64> {
+    let bytes = data.get(pos..pos.checked_add(size)?)?;
+    let mut val: u64 = 0;
+    for b in bytes {
+        val = val << 8 | *b as u64;
+    }
+
+    // Sign extend
+    let shift = 64 - size * 8;
+    Some(((val << shift) as i64) >> shift)
+}
+
+// Parses one header and data block of a TZif file (RFC 8536),
+// returning the zone and the offset of the end of the block
+fn parse_tzif_block(data: &[u8], start: usize, time_size: usize) -> Option<(Zone, usize)> {
+    if data.get(start..start + 4)? != b"TZif" {
+        return None;
+    }
+
+    let count = |n: usize| read_u32(data, start + 20 + n * 4).map(|c| c as usize);
+    let isutcnt = count(0)?;
+    let isstdcnt = count(1)?;
+    let leapcnt = count(2)?;
+    let timecnt = count(3)?;
+    let typecnt = count(4)?;
+    let charcnt = count(5)?;
+
+    // The counts come from the file, so a corrupt file could ask for
+    // anything; the whole block has to fit before anything is allocated
+    let times_pos = start.checked_add(44)?;
+    let idx_pos = times_pos.checked_add(timecnt.checked_mul(time_size)?)?;
+    let types_pos = idx_pos.checked_add(timecnt)?;
+    let chars_pos = types_pos.checked_add(typecnt.checked_mul(6)?)?;
+    let end = chars_pos
+        .checked_add(charcnt)?
+        .checked_add(leapcnt.checked_mul(time_size + 4)?)?
+        .checked_add(isstdcnt)?
+        .checked_add(isutcnt)?;
+    if end > data.len() {
+        return None;
+    }
+    let chars = &data[chars_pos..chars_pos + charcnt];
+
+    let mut transitions = Vec::with_capacity(timecnt);
+    for i in 0..timecnt {
+        let ts = read_be(data, times_pos + i * time_size, time_size)?;
+        let idx = *data.get(idx_pos + i)? as usize;
+        transitions.push((ts, idx));
+    }
+
+    let mut types = Vec::with_capacity(typecnt);
+    for i in 0..typecnt {
+        let pos = types_pos + i * 6;
+        let utoff = read_be(data, pos, 4)?;
+        let abbrind = *data.get(pos + 5)? as usize;
+        let abbrev = chars.get(abbrind..).unwrap_or(&[]);
+        let len = abbrev.iter().position(|b| *b == 0).unwrap_or(abbrev.len());
+        types.push(LocalType {
+            utoff,
+            abbrev: String::from_utf8_lossy(&abbrev[..len]).to_string(),
+        });
+    }
+
+    let zone = Zone {
+        transitions,
+        types,
+        footer: None,
+    };
+    Some((zone, end))
+}
+
+fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    let (zone, end) = parse_tzif_block(data, 0, 4)?;
+    if *data.get(4)? < b'2' {
+        return Some(zone);
+    }
+
+    // Version 2 and up repeat the data with 64-bit times,
+    // followed by a footer like '\nCET-1CEST,M3.5.0,M10.5.0/3\n'
+    let (mut zone, end) = parse_tzif_block(data, end, 8)?;
+    let footer = data.get(end..).unwrap_or(&[]);
+    let footer = String::from_utf8_lossy(footer);
+    zone.footer = parse_posix_tz(footer.trim_matches('\n'));
+    Some(zone)
+}
+
+fn read_tzif(path: &Path) -> Option<Zone> {
+    parse_tzif(&fs::read(path).ok()?)
+}
+
+// Follows the same rules as the C library: no TZ means /etc/localtime,
+// TZ can name a file in the zoneinfo directory (optionally prefixed
+// with a ':'), or it can be a POSIX TZ string
+fn local_zone() -> Zone {
+    let tz = match env::var("TZ") {
+        Ok(tz) => tz,
+        Err(..) => return read_tzif(Path::new("/etc/localtime")).unwrap_or_else(Zone::utc),
+    };
+
+    let name = tz.strip_prefix(':').unwrap_or(&tz);
+    if name.is_empty() {
+        return Zone::utc();
+    }
+
+    let path = if name.starts_with('/') {
+        PathBuf::from(name)
+    } else {
+        let dir = env::var_os("TZDIR").unwrap_or_else(|| "/usr/share/zoneinfo".into());
+        PathBuf::from(dir).join(name)
+    };
+
+    if let Some(zone) = read_tzif(&path) {
+        return zone;
+    }
+
+    match parse_posix_tz(name) {
+        Some(posix) => Zone {
+            transitions: Vec::new(),
+            types: Vec::new(),
+            footer: Some(posix),
+        },
+        None => Zone::utc(),
+    }
+}
+
+fn zone_time(zone: &Zone, ts: i64) -> Tm {
+    let ts = ts.clamp(-MAX_TS, MAX_TS);
+    let t = zone.lookup(ts);
+    break_down(ts, t.utoff, t.abbrev)
+}
+
+pub fn local_time(ts: i64) -> Tm {
+    zone_time(&local_zone(), ts)
+}
+
+pub fn utc_time(ts: i64) -> Tm {
+    zone_time(&Zone::utc(), ts)
+}
+
+// Supports the common strftime(3) conversions.
+// Unknown conversions are output as-is.
+pub fn strftime(fmt: &str, tm: &Tm) -> String {
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    let hour12 = match tm.hour % 12 {
+        0 => 12,
+        h => h,
+    };
+
+    while let Some(ch) = chars.next() {
+        if ch != '%' {
+            out.push(ch);
+            continue;
+        }
+
+        let conv = match chars.next() {
+            Some(conv) => conv,
+            None => {
+                out.push('%');
+                break;
+            }
+        };
+
+        let s = match conv {
+            'a' => WEEKDAYS[tm.wday as usize][..3].to_string(),
+            'A' => WEEKDAYS[tm.wday as usize].to_string(),
+            'b' | 'h' => MONTHS[tm.month as usize - 1][..3].to_string(),
+            'B' => MONTHS[tm.month as usize - 1].to_string(),
+            'c' => strftime("%a %b %e %H:%M:%S %Y", tm),
+            'C' => format!("{:02}", tm.year.div_euclid(100)),
+            'd' => format!("{:02}", tm.day),
+            'D' => strftime("%m/%d/%y", tm),
+            'e' => format!("{:2}", tm.day),
+            'F' => strftime("%Y-%m-%d", tm),
+            'H' => format!("{:02}", tm.hour),
+            'I' => format!("{:02}", hour12),
+            'j' => format!("{:03}", tm.yday + 1),
+            'k' => format!("{:2}", tm.hour),
+            'l' => format!("{:2}", hour12),
+            'm' => format!("{:02}", tm.month),
+            'M' => format!("{:02}", tm.min),
+            'n' => "\n".to_string(),
+            'p' => if tm.hour < 12 { "AM" } else { "PM" }.to_string(),
+            'P' => if tm.hour < 12 { "am" } else { "pm" }.to_string(),
+            'r' => strftime("%I:%M:%S %p", tm),
+            'R' => strftime("%H:%M", tm),
+            's' => format!("{}", tm.ts),
+            'S' => format!("{:02}", tm.sec),
+            't' => "\t".to_string(),
+            'T' => strftime("%H:%M:%S", tm),
+            'u' => format!("{}", if tm.wday == 0 { 7 } else { tm.wday }),
+            'U' => format!("{:02}", (tm.yday + 7 - tm.wday) / 7),
+            'w' => format!("{}", tm.wday),
+            'W' => format!("{:02}", (tm.yday + 7 - (tm.wday + 6) % 7) / 7),
+            'y' => format!("{:02}", tm.year.rem_euclid(100)),
+            'Y' => format!("{}", tm.year),
+            'z' => {
+                let sign = if tm.utoff < 0 { '-' } else { '+' };
+                let off = tm.utoff.abs();
+                format!("{}{:02}{:02}", sign, off / 3600, off / 60 % 60)
+            }
+            'Z' => tm.zone.clone(),
+            '%' => "%".to_string(),
+            _ => format!("%{}", conv),
+        };
+        out.push_str(&s);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn posix_zone(tz: &str) -> Zone {
+        Zone {
+            transitions: Vec::new(),
+            types: Vec::new(),
+            footer: Some(parse_posix_tz(tz).unwrap()),
+        }
+    }
+
+    fn format(zone: &Zone, ts: i64) -> String {
+        strftime("%F %T %Z %z", &zone_time(zone, ts))
+    }
+
+    // A version 1 TZif file with the given transitions and types
+    fn tzif(transitions: &[(i32, u8)], types: &[(i32, &str)]) -> Vec<u8> {
+        let mut chars = Vec::new();
+        let mut ttinfos = Vec::new();
+        for (utoff, abbrev) in types {
+            ttinfos.extend_from_slice(&utoff.to_be_bytes());
+            ttinfos.push(0);
+            ttinfos.push(chars.len() as u8);
+            chars.extend_from_slice(abbrev.as_bytes());
+            chars.push(0);
+        }
+
+        let mut data = b"TZif\0".to_vec();
+        data.extend_from_slice(&[0; 15]);
+        for count in [0, 0, 0, transitions.len(), types.len(), chars.len()] {
+            data.extend_from_slice(&(count as u32).to_be_bytes());
+        }
+        for (ts, _) in transitions {
+            data.extend_from_slice(&ts.to_be_bytes());
+        }
+        data.extend(transitions.iter().map(|(_, idx)| *idx));
+        data.extend_from_slice(&ttinfos);
+        data.extend_from_slice(&chars);
+        data
+    }
+
+    #[test]
+    fn northern_hemisphere_rule() {
+        let zone = posix_zone("CET-1CEST,M3.5.0,M10.5.0/3");
+        assert_eq!(format(&zone, 1705276800), "2024-01-15 01:00:00 CET +0100");
+        assert_eq!(format(&zone, 1721001600), "2024-07-15 02:00:00 CEST +0200");
+    }
+
+    #[test]
+    fn southern_hemisphere_rule() {
+        // Daylight saving time starts in October and ends in April
+        let zone = posix_zone("AEST-10AEDT,M10.1.0,M4.1.0/3");
+        assert_eq!(format(&zone, 1705276800), "2024-01-15 11:00:00 AEDT +1100");
+        assert_eq!(format(&zone, 1712419199), "2024-04-07 02:59:59 AEDT +1100");
+        assert_eq!(format(&zone, 1712419200), "2024-04-07 02:00:00 AEST +1000");
+        assert_eq!(format(&zone, 1728143999), "2024-10-06 01:59:59 AEST +1000");
+        assert_eq!(format(&zone, 1728144000), "2024-10-06 03:00:00 AEDT +1100");
+    }
+
+    #[test]
+    fn posix_tz_errors() {
+        assert!(parse_posix_tz("UTC0").is_some());
+        assert!(parse_posix_tz("<-03>3").is_some());
+        assert!(parse_posix_tz("X0").is_none());
+        assert!(parse_posix_tz("CET-999999999999999999").is_none());
+        assert!(parse_posix_tz("CET-1CEST,M3.9.0,M10.5.0").is_none());
+        assert!(parse_posix_tz("CET-1CEST,M3.5.0,M10.5.0/99999999999").is_none());
+    }
+
+    #[test]
+    fn week_numbers_at_year_boundaries() {
+        // Checked against GNU date
+        let cases = [
+            ("2022-01-01", "Sat 00 00"),
+            ("2022-12-31", "Sat 52 52"),
+            ("2023-01-01", "Sun 01 00"),
+            ("2023-12-31", "Sun 53 52"),
+            ("2024-01-01", "Mon 00 01"),
+            ("2024-12-31", "Tue 52 53"),
+            ("2025-01-05", "Sun 01 00"),
+        ];
+
+        for (date, expected) in cases {
+            let (y, m, d) = (&date[0..4], &date[5..7], &date[8..10]);
+            let days = days_from_civil(y.parse().unwrap(), m.parse().unwrap(), d.parse().unwrap());
+            let tm = utc_time(days * 86400);
+            assert_eq!(strftime("%a %U %W", &tm), expected, "{}", date);
+        }
+    }
+
+    #[test]
+    fn huge_timestamps() {
+        let zone = posix_zone("AEST-10AEDT,M10.1.0,M4.1.0/3");
+        for ts in [i64::MAX, i64::MIN, 1e300 as i64, -1e300 as i64] {
+            zone_time(&zone, ts);
+            utc_time(ts);
+        }
+        assert_eq!(utc_time(i64::MAX).ts, MAX_TS);
+    }
+
+    #[test]
+    fn parses_tzif() {
+        let data = tzif(&[(0, 1), (100, 0)], &[(3600, "AAA"), (-7200, "BBB")]);
+        let zone = parse_tzif(&data).unwrap();
+        assert_eq!(format(&zone, -1), "1970-01-01 00:59:59 AAA +0100");
+        assert_eq!(format(&zone, 0), "1969-12-31 22:00:00 BBB -0200");
+        assert_eq!(format(&zone, 100), "1970-01-01 01:01:40 AAA +0100");
+    }
+
+    #[test]
+    fn corrupt_tzif() {
+        let data = tzif(&[(0, 1)], &[(3600, "AAA"), (-7200, "BBB")]);
+        for len in 0..data.len() {
+            assert!(parse_tzif(&data[..len]).is_none());
+        }
+
+        // Counts as large as they go mustn't be trusted for allocations
+        for n in 0..6 {
+            let mut data = data.clone();
+            data[20 + n * 4..24 + n * 4].copy_from_slice(&[0xff; 4]);
+            assert!(parse_tzif(&data).is_none());
+        }
+    }
+}
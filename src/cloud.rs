@@ -0,0 +1,456 @@
+use super::profile;
+use super::timeout;
+use super::util::{self, non_empty_var};
+use osyris::eval::Scope;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Where each cloud CLI keeps its configuration.
+// Everything is read from files; running 'aws', 'gcloud' or 'az'
+// would take hundreds of milliseconds.
+#[derive(Clone)]
+struct CloudCtx {
+    aws_config: Option<PathBuf>,
+    gcloud_dir: Option<PathBuf>,
+    azure_dir: Option<PathBuf>,
+}
+
+fn path_var(key: &str) -> Option<PathBuf> {
+    util::non_empty_var_os(key).map(PathBuf::from)
+}
+
+impl CloudCtx {
+    fn from_env() -> Self {
+        let home = dirs::home_dir();
+        let in_home = |parts: &[&str]| {
+            home.as_ref().map(|home| {
+                let mut path = home.clone();
+                for part in parts {
+                    path.push(part);
+                }
+                path
+            })
+        };
+
+        Self {
+            aws_config: path_var("AWS_CONFIG_FILE").or_else(|| in_home(&[".aws", "config"])),
+            gcloud_dir: path_var("CLOUDSDK_CONFIG").or_else(|| in_home(&[".config", "gcloud"])),
+            azure_dir: path_var("AZURE_CONFIG_DIR").or_else(|| in_home(&[".azure"])),
+        }
+    }
+}
+
+// Parses the INI-style files used by both the AWS CLI and gcloud
+// into a map of section name -> key -> value
+fn read_ini(path: &Path) -> Option<HashMap<String, HashMap<String, String>>> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut sections = HashMap::new();
+    let mut section = String::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            section = name.trim().to_string();
+            continue;
+        }
+
+        if let Some((key, val)) = line.split_once('=') {
+            sections
+                .entry(section.clone())
+                .or_insert_with(HashMap::new)
+                .insert(key.trim().to_string(), val.trim().to_string());
+        }
+    }
+
+    Some(sections)
+}
+
+fn aws_profile_name() -> Option<String> {
+    // aws-vault exports the profile it's running under as AWS_VAULT
+    non_empty_var("AWS_VAULT")
+        .or_else(|| non_empty_var("AWS_PROFILE"))
+        .or_else(|| non_empty_var("AWS_DEFAULT_PROFILE"))
+}
+
+fn aws_profile(_: &CloudCtx) -> Option<String> {
+    aws_profile_name()
+}
+
+fn aws_region(ctx: &CloudCtx) -> Option<String> {
+    if let Some(region) =
+        non_empty_var("AWS_REGION").or_else(|| non_empty_var("AWS_DEFAULT_REGION"))
+    {
+        return Some(region);
+    }
+
+    let profile = aws_profile_name().unwrap_or_else(|| "default".to_string());
+
+    // In ~/.aws/config, every profile except 'default' is called '[profile <name>]'
+    let section = match profile.as_str() {
+        "default" => profile,
+        _ => format!("profile {}", profile),
+    };
+
+    let ini = read_ini(ctx.aws_config.as_ref()?)?;
+    ini.get(&section)?.get("region").cloned()
+}
+
+fn gcloud_config_name(ctx: &CloudCtx) -> Option<String> {
+    if let Some(name) = non_empty_var("CLOUDSDK_ACTIVE_CONFIG_NAME") {
+        return Some(name);
+    }
+
+    let content = fs::read_to_string(ctx.gcloud_dir.as_ref()?.join("active_config")).ok()?;
+    let name = content.trim();
+    if name.is_empty() {
+        None
+    } else {
+        Some(name.to_string())
+    }
+}
+
+fn gcloud_project(ctx: &CloudCtx) -> Option<String> {
+    if let Some(project) = non_empty_var("CLOUDSDK_CORE_PROJECT") {
+        return Some(project);
+    }
+
+    let name = gcloud_config_name(ctx)?;
+    let mut path = ctx.gcloud_dir.clone()?;
+    path.push("configurations");
+    path.push(format!("config_{}", name));
+    let ini = read_ini(&path)?;
+    ini.get("core")?.get("project").cloned()
+}
+
+enum Json {
+    Null,
+    Bool(bool),
+    Number,
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+// Just enough JSON to read azureProfile.json.
+// Numbers are validated but their values are thrown away.
+struct JsonParser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+}
+
+impl<'a> JsonParser<'a> {
+    fn skip_whitespace(&mut self) {
+        while let Some(ch) = self.chars.peek() {
+            if !ch.is_whitespace() {
+                break;
+            }
+            self.chars.next();
+        }
+    }
+
+    fn expect_word(&mut self, word: &str) -> Option<()> {
+        for expected in word.chars() {
+            if self.chars.next()? != expected {
+                return None;
+            }
+        }
+        Some(())
+    }
+
+    fn parse_string(&mut self) -> Option<String> {
+        let mut s = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(s),
+                '\\' => match self.chars.next()? {
+                    'n' => s.push('\n'),
+                    't' => s.push('\t'),
+                    'r' => s.push('\r'),
+                    'b' => s.push('\x08'),
+                    'f' => s.push('\x0c'),
+                    'u' => {
+                        let hex: String = (0..4).filter_map(|_| self.chars.next()).collect();
+                        let code = u32::from_str_radix(&hex, 16).ok()?;
+                        s.push(char::from_u32(code).unwrap_or('\u{fffd}'));
+                    }
+                    ch => s.push(ch),
+                },
+                ch => s.push(ch),
+            }
+        }
+    }
+
+    fn parse_value(&mut self) -> Option<Json> {
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            '{' => {
+                self.chars.next();
+                let mut entries = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match self.chars.next()? {
+                        '}' => return Some(Json::Object(entries)),
+                        ',' => continue,
+                        '"' => {
+                            let key = self.parse_string()?;
+                            self.skip_whitespace();
+                            if self.chars.next()? != ':' {
+                                return None;
+                            }
+                            entries.push((key, self.parse_value()?));
+                        }
+                        _ => return None,
+                    }
+                }
+            }
+            '[' => {
+                self.chars.next();
+                let mut items = Vec::new();
+                loop {
+                    self.skip_whitespace();
+                    match *self.chars.peek()? {
+                        ']' => {
+                            self.chars.next();
+                            return Some(Json::Array(items));
+                        }
+                        ',' => {
+                            self.chars.next();
+                        }
+                        _ => items.push(self.parse_value()?),
+                    }
+                }
+            }
+            '"' => {
+                self.chars.next();
+                Some(Json::String(self.parse_string()?))
+            }
+            't' => self.expect_word("true").map(|_| Json::Bool(true)),
+            'f' => self.expect_word("false").map(|_| Json::Bool(false)),
+            'n' => self.expect_word("null").map(|_| Json::Null),
+            ch if ch == '-' || ch.is_ascii_digit() => {
+                while let Some(ch) = self.chars.peek() {
+                    if !(ch.is_ascii_digit() || "+-.eE".contains(*ch)) {
+                        break;
+                    }
+                    self.chars.next();
+                }
+                Some(Json::Number)
+            }
+            _ => None,
+        }
+    }
+}
+
+fn azure_subscription(ctx: &CloudCtx) -> Option<String> {
+    let content = fs::read_to_string(ctx.azure_dir.as_ref()?.join("azureProfile.json")).ok()?;
+
+    // The Azure CLI writes this file with a UTF-8 byte order mark
+    let content = content.trim_start_matches('\u{feff}');
+    let mut parser = JsonParser {
+        chars: content.chars().peekable(),
+    };
+
+    let profile = parser.parse_value()?;
+    let subscriptions = match profile.get("subscriptions")? {
+        Json::Array(subscriptions) => subscriptions,
+        _ => return None,
+    };
+
+    let default = subscriptions
+        .iter()
+        .find(|sub| matches!(sub.get("isDefault"), Some(Json::Bool(true))))?;
+    match default.get("name") {
+        Some(Json::String(name)) => Some(name.clone()),
+        _ => None,
+    }
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(CloudCtx::from_env());
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap(
+                    $name,
+                    Rc::new(move |_, scope| {
+                        let ctx = CloudCtx::clone(&c);
+                        let val = timeout::run(move || $func(&ctx)).flatten();
+                        Ok((util::string_or_none(val.as_deref()), scope))
+                    }),
+                ),
+            );
+        };
+    }
+
+    put!("aws-profile", aws_profile);
+    put!("aws-region", aws_region);
+    put!("gcloud-config", gcloud_config_name);
+    put!("gcloud-project", gcloud_project);
+    put!("azure-subscription", azure_subscription);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{fixture, ENV_LOCK};
+    use std::env;
+
+    const VARS: &[&str] = &[
+        "AWS_VAULT",
+        "AWS_PROFILE",
+        "AWS_DEFAULT_PROFILE",
+        "AWS_REGION",
+        "AWS_DEFAULT_REGION",
+        "CLOUDSDK_ACTIVE_CONFIG_NAME",
+        "CLOUDSDK_CORE_PROJECT",
+    ];
+
+    fn clear_env() {
+        for var in VARS {
+            env::remove_var(var);
+        }
+    }
+
+    fn ctx(dir: &Path) -> CloudCtx {
+        CloudCtx {
+            aws_config: Some(dir.join(".aws/config")),
+            gcloud_dir: Some(dir.join(".config/gcloud")),
+            azure_dir: Some(dir.join(".azure")),
+        }
+    }
+
+    #[test]
+    fn aws() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        clear_env();
+        let dir = fixture(&[(
+            ".aws/config",
+            "# comment\n\
+             [default]\n\
+             region = us-east-1\n\
+             \n\
+             [profile work]\n\
+             ; another comment\n\
+             output=json\n\
+             region=eu-west-1\n\
+             \n\
+             [profile noregion]\n\
+             output = json\n",
+        )]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(aws_profile(&ctx), None);
+        assert_eq!(aws_region(&ctx).as_deref(), Some("us-east-1"));
+
+        env::set_var("AWS_PROFILE", "work");
+        assert_eq!(aws_profile(&ctx).as_deref(), Some("work"));
+        assert_eq!(aws_region(&ctx).as_deref(), Some("eu-west-1"));
+
+        // aws-vault's variable wins over AWS_PROFILE
+        env::set_var("AWS_VAULT", "noregion");
+        assert_eq!(aws_profile(&ctx).as_deref(), Some("noregion"));
+        assert_eq!(aws_region(&ctx), None);
+
+        env::set_var("AWS_DEFAULT_REGION", "ap-south-1");
+        assert_eq!(aws_region(&ctx).as_deref(), Some("ap-south-1"));
+        env::set_var("AWS_REGION", "sa-east-1");
+        assert_eq!(aws_region(&ctx).as_deref(), Some("sa-east-1"));
+
+        clear_env();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gcloud() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        clear_env();
+        let dir = fixture(&[
+            (".config/gcloud/active_config", "work\n"),
+            (
+                ".config/gcloud/configurations/config_work",
+                "[core]\naccount = me@example.com\nproject = work-project\n",
+            ),
+            (
+                ".config/gcloud/configurations/config_default",
+                "[compute]\nzone = us-east1-b\n",
+            ),
+        ]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(gcloud_config_name(&ctx).as_deref(), Some("work"));
+        assert_eq!(gcloud_project(&ctx).as_deref(), Some("work-project"));
+
+        env::set_var("CLOUDSDK_ACTIVE_CONFIG_NAME", "default");
+        assert_eq!(gcloud_config_name(&ctx).as_deref(), Some("default"));
+        assert_eq!(gcloud_project(&ctx), None);
+
+        env::set_var("CLOUDSDK_CORE_PROJECT", "env-project");
+        assert_eq!(gcloud_project(&ctx).as_deref(), Some("env-project"));
+
+        clear_env();
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn gcloud_without_active_config() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        clear_env();
+        let dir = fixture(&[(".config/gcloud/active_config", "  \n")]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(gcloud_config_name(&ctx), None);
+        assert_eq!(gcloud_project(&ctx), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn azure() {
+        let dir = fixture(&[(
+            ".azure/azureProfile.json",
+            "\u{feff}{\"installationId\": \"x\", \"subscriptions\": [\n\
+             {\"id\": \"1\", \"name\": \"Old\", \"isDefault\": false, \"quota\": -1.5e3},\n\
+             {\"id\": \"2\", \"name\": \"Caf\\u00e9 \\\"prod\\\"\", \"isDefault\": true,\n\
+              \"tenantDefaultDomain\": null, \"managedByTenants\": []}\n\
+             ]}",
+        )]);
+
+        assert_eq!(
+            azure_subscription(&ctx(&dir)).as_deref(),
+            Some("Café \"prod\"")
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_files() {
+        let dir = fixture(&[]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(azure_subscription(&ctx), None);
+        assert_eq!(
+            gcloud_project(&CloudCtx {
+                gcloud_dir: None,
+                ..ctx
+            }),
+            None
+        );
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -1,18 +1,19 @@
 use super::state::State;
+use super::util;
 use super::UncountedString;
 use osyris::eval::{Scope, StackTrace, ValRef};
 use std::cell::RefCell;
 use std::rc::Rc;
 
 const BLACK: &'static str = "\x1b[30m";
-const RED: &'static str = "\x1b[31m";
+pub const RED: &'static str = "\x1b[31m";
 const GREEN: &'static str = "\x1b[32m";
 const YELLOW: &'static str = "\x1b[33m";
 const BLUE: &'static str = "\x1b[34m";
 const MAGENTA: &'static str = "\x1b[35m";
 const CYAN: &'static str = "\x1b[36m";
 const WHITE: &'static str = "\x1b[37m";
-const RESET: &'static str = "\x1b[0m";
+pub const RESET: &'static str = "\x1b[0m";
 
 const BOLD_BLACK: &'static str = "\x1b[30;1m";
 const BOLD_RED: &'static str = "\x1b[31;1m";
@@ -88,6 +89,45 @@ fn color(
     Ok(ValRef::List(Rc::new(RefCell::new(ret))))
 }
 
+// Colors used by 'hash-color'; black and white are left out,
+// since one of them is bound to be unreadable on the user's background
+const HASH_COLORS: [&'static str; 12] = [
+    RED,
+    GREEN,
+    YELLOW,
+    BLUE,
+    MAGENTA,
+    CYAN,
+    BOLD_RED,
+    BOLD_GREEN,
+    BOLD_YELLOW,
+    BOLD_BLUE,
+    BOLD_MAGENTA,
+    BOLD_CYAN,
+];
+
+fn hash_color(ctx: &Rc<RefCell<ColorCtx>>, mut args: Vec<ValRef>) -> Result<ValRef, StackTrace> {
+    if args.is_empty() {
+        return Err(StackTrace::from_str(
+            "'hash-color' requires at least 1 argument",
+        ));
+    }
+
+    let key = match args.remove(0) {
+        ValRef::String(s) => s,
+        _ => {
+            return Err(StackTrace::from_str(
+                "'hash-color' requires a string as its first argument",
+            ))
+        }
+    };
+
+    // FNV-1a, because the result has to stay the same across
+    // Rust versions for a host to keep its color
+    let col = HASH_COLORS[util::fnv1a_32(key.as_bytes()) as usize % HASH_COLORS.len()];
+    color(ctx, col, args)
+}
+
 pub fn init(mut scope: Scope, state: &Rc<State>) -> Scope {
     let ctx = Rc::new(RefCell::new(ColorCtx::new(state.clone())));
 
@@ -120,5 +160,10 @@ pub fn init(mut scope: Scope, state: &Rc<State>) -> Scope {
     put!("bold-cyan", BOLD_CYAN);
     put!("bold-white", BOLD_WHITE);
 
+    scope = scope.put_func(
+        "hash-color",
+        Rc::new(move |a, scope| Ok((hash_color(&ctx, a)?, scope))),
+    );
+
     scope
 }
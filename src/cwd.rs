@@ -0,0 +1,302 @@
+use super::profile;
+use super::sys;
+use super::timeout;
+use osyris::bstring::BString;
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::env;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf, MAIN_SEPARATOR};
+use std::rc::Rc;
+
+struct CwdCtx {
+    // Pairs of (prefix, replacement), like ('/home/user', '~')
+    substitutions: Vec<(PathBuf, OsString)>,
+    fs_type: Option<Option<String>>,
+}
+
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), dirs::home_dir()) {
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+impl CwdCtx {
+    fn new() -> Self {
+        let mut substitutions = Vec::new();
+        if let Some(home) = dirs::home_dir() {
+            substitutions.push((home, OsString::from("~")));
+        }
+
+        Self {
+            substitutions,
+            fs_type: None,
+        }
+    }
+
+    // Replaces the longest matching prefix. Path::strip_prefix works on
+    // whole components, so '/home/user' doesn't match '/home/username'.
+    // Only one substitution is ever applied.
+    fn substitute(&self, path: &Path) -> BString {
+        let best = self
+            .substitutions
+            .iter()
+            .filter_map(|(prefix, replacement)| {
+                let rest = path.strip_prefix(prefix).ok()?;
+                Some((prefix.components().count(), replacement, rest))
+            })
+            .max_by_key(|(len, _, _)| *len);
+
+        match best {
+            Some((_, replacement, rest)) => {
+                let mut s = replacement.clone();
+                if !rest.as_os_str().is_empty() {
+                    s.push(MAIN_SEPARATOR.to_string());
+                    s.push(rest.as_os_str());
+                }
+                BString::from_os_str(&s)
+            }
+            None => BString::from_os_str(path.as_os_str()),
+        }
+    }
+}
+
+// The cwd as the shell sees it, with symlinks intact. $PWD may be stale
+// or inherited from somewhere else, so it's only used if it refers
+// to the same directory as the physical cwd.
+// If the cwd has been deleted, $PWD is the only name we have for it.
+fn find_logical_cwd() -> Option<PathBuf> {
+    let pwd = match env::var_os("PWD") {
+        Some(pwd) if Path::new(&pwd).is_absolute() => Some(PathBuf::from(pwd)),
+        _ => None,
+    };
+
+    match (env::current_dir(), pwd) {
+        (Ok(physical), Some(pwd)) if sys::same_file(&physical, &pwd) => Some(pwd),
+        (Ok(physical), _) => Some(physical),
+        (Err(..), pwd) => pwd,
+    }
+}
+
+// Comparing with $PWD stats it, so it's done on another thread;
+// the physical cwd comes from the kernel without touching the file system
+fn logical_cwd() -> Option<PathBuf> {
+    match timeout::run(find_logical_cwd) {
+        Some(cwd) => cwd,
+        None => env::current_dir().ok(),
+    }
+}
+
+// Where to start walking up from when looking for a repo or a version file:
+// the logical cwd first, so that what's found matches what 'cwd' shows,
+// and then the physical cwd, in case the logical cwd is a symlink
+// into the middle of a repo
+pub fn search_starts() -> Vec<PathBuf> {
+    let mut starts: Vec<PathBuf> = logical_cwd().into_iter().collect();
+    if let Ok(physical) = env::current_dir() {
+        if !starts.contains(&physical) {
+            starts.push(physical);
+        }
+    }
+    starts
+}
+
+// The cwd with only the default substitutions,
+// for when there's no config to add any
+pub fn default_cwd() -> BString {
+    match logical_cwd() {
+        Some(wd) => CwdCtx::new().substitute(&wd),
+        None => BString::from_str(""),
+    }
+}
+
+fn cwd(ctx: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    let wd = match logical_cwd() {
+        Some(wd) => ctx.borrow().substitute(&wd),
+        None => BString::from_str(""),
+    };
+
+    Ok(ValRef::String(Rc::new(wd)))
+}
+
+fn cwd_physical(ctx: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    match env::current_dir() {
+        Ok(wd) => Ok(ValRef::String(Rc::new(ctx.borrow().substitute(&wd)))),
+        Err(..) => Ok(ValRef::None),
+    }
+}
+
+fn cwd_deleted(_: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    // getcwd() fails with ENOENT when the directory has been removed
+    Ok(ValRef::Bool(env::current_dir().is_err()))
+}
+
+fn cwd_writable(_: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    let wd = match env::current_dir() {
+        Ok(wd) => wd,
+        Err(..) => return Ok(ValRef::Bool(false)),
+    };
+
+    match timeout::run(move || sys::is_writable(&wd)) {
+        Some(writable) => Ok(ValRef::Bool(writable)),
+        None => Ok(ValRef::None),
+    }
+}
+
+fn cwd_owner(_: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    let wd = match env::current_dir() {
+        Ok(wd) => wd,
+        Err(..) => return Ok(ValRef::None),
+    };
+
+    // The name comes from NSS, which might ask an LDAP or SSSD server
+    let name = timeout::run(move || {
+        let uid = sys::file_owner(&wd)?;
+        Some(sys::user_name(uid).unwrap_or_else(|| format!("{}", uid)))
+    });
+    match name.flatten() {
+        Some(name) => Ok(ValRef::String(Rc::new(BString::from_string(name)))),
+        None => Ok(ValRef::None),
+    }
+}
+
+fn fs_type(ctx: &Rc<RefCell<CwdCtx>>) -> Option<String> {
+    if let Some(fs_type) = &ctx.borrow().fs_type {
+        return fs_type.clone();
+    }
+
+    let fs_type = env::current_dir()
+        .ok()
+        .and_then(|wd| sys::mount_fs_type(Path::new("/proc/self/mountinfo"), &wd));
+    ctx.borrow_mut().fs_type = Some(fs_type.clone());
+    fs_type
+}
+
+fn cwd_fs_type(ctx: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    match fs_type(ctx) {
+        Some(fs_type) => Ok(ValRef::String(Rc::new(BString::from_string(fs_type)))),
+        None => Ok(ValRef::None),
+    }
+}
+
+// Network file systems, where things like 'git status' get slow
+fn is_remote_fs(fs_type: &str) -> bool {
+    match fs_type {
+        "nfs" | "nfs4" | "cifs" | "smb3" | "smbfs" | "sshfs" | "9p" | "afs" | "ceph"
+        | "glusterfs" | "fuse" => true,
+        _ => fs_type.starts_with("fuse."),
+    }
+}
+
+fn cwd_is_remote_fs(ctx: &Rc<RefCell<CwdCtx>>) -> Result<ValRef, StackTrace> {
+    match fs_type(ctx) {
+        Some(fs_type) => Ok(ValRef::Bool(is_remote_fs(&fs_type))),
+        None => Ok(ValRef::Bool(false)),
+    }
+}
+
+// (path-substitute "~/src/company" "@co")
+fn path_substitute(ctx: &Rc<RefCell<CwdCtx>>, args: Vec<ValRef>) -> Result<ValRef, StackTrace> {
+    if args.len() != 2 {
+        return Err(StackTrace::from_str(
+            "'path-substitute' requires 2 arguments",
+        ));
+    }
+
+    let (prefix, replacement) = match (&args[0], &args[1]) {
+        (ValRef::String(prefix), ValRef::String(replacement)) => (prefix, replacement),
+        _ => {
+            return Err(StackTrace::from_str(
+                "'path-substitute' requires string arguments",
+            ))
+        }
+    };
+
+    let prefix = expand_home(Path::new(&prefix.to_os_str()));
+    let replacement = replacement.to_os_str().to_os_string();
+
+    let mut c = ctx.borrow_mut();
+    c.substitutions.retain(|(p, _)| *p != prefix);
+    c.substitutions.push((prefix, replacement));
+    Ok(ValRef::None)
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(CwdCtx::new()));
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
+        };
+    }
+
+    put!("cwd", cwd);
+    put!("cwd-physical", cwd_physical);
+    put!("cwd-deleted?", cwd_deleted);
+    put!("cwd-writable?", cwd_writable);
+    put!("cwd-owner", cwd_owner);
+    put!("cwd-fs-type", cwd_fs_type);
+    put!("cwd-is-remote-fs?", cwd_is_remote_fs);
+
+    scope = scope.put_func(
+        "path-substitute",
+        Rc::new(move |args, scope| Ok((path_substitute(&ctx, args)?, scope))),
+    );
+
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ctx(substitutions: &[(&str, &str)]) -> CwdCtx {
+        CwdCtx {
+            substitutions: substitutions
+                .iter()
+                .map(|(prefix, replacement)| (PathBuf::from(prefix), OsString::from(replacement)))
+                .collect(),
+            fs_type: None,
+        }
+    }
+
+    #[test]
+    fn substitutes() {
+        let subs = [
+            ("/home/foo", "~"),
+            ("/home/foo/src/co", "@co"),
+            ("/srv", ""),
+        ];
+        let reversed: Vec<_> = subs.iter().rev().copied().collect();
+        for ctx in [ctx(&subs), ctx(&reversed)] {
+            for (path, expected) in [
+                // Exact matches
+                ("/home/foo", "~"),
+                ("/home/foo/src/co", "@co"),
+                // The longest prefix wins, whatever the order
+                ("/home/foo/src/co/app", "@co/app"),
+                ("/home/foo/src", "~/src"),
+                ("/home/foo/src/company", "~/src/company"),
+                // Prefixes only match whole components
+                ("/home/foobar", "/home/foobar"),
+                ("/home/foobar/x", "/home/foobar/x"),
+                ("/home", "/home"),
+                // An empty replacement keeps the separator
+                ("/srv/www", "/www"),
+                ("/", "/"),
+            ] {
+                assert_eq!(
+                    ctx.substitute(Path::new(path)).to_string(),
+                    expected,
+                    "{}",
+                    path
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,41 @@
+use super::color;
+use super::cwd;
+use super::state::State;
+use super::sys;
+
+// This prompt has to work when nothing else does
+const UNKNOWN_HOST: &str = "localhost";
+
+// Used when the config can't be read, parsed, evaluated or rendered.
+// A typo in the config shouldn't leave the user without a usable prompt,
+// so this prints the full error to stderr, and a simple 'user@host cwd $'
+// prompt with a one-line error marker to stdout.
+pub fn print(state: &State, err: &str) {
+    eprintln!("{}", err);
+
+    let shell = state.shell;
+    let marker = err.lines().next().unwrap_or("");
+    let prompt_char = if sys::euid() == Some(0) { '#' } else { '$' };
+    let cwd = cwd::default_cwd();
+
+    println!(
+        "{}{}{}[starstruck] {}{}{}{}",
+        shell.escape_start(),
+        color::RED,
+        shell.escape_end(),
+        shell.escape_text(marker),
+        shell.escape_start(),
+        color::RESET,
+        shell.escape_end(),
+    );
+    print!(
+        "{} {} ",
+        shell.escape_text(&format!(
+            "{}@{} {}",
+            sys::username(),
+            sys::hostname().as_deref().unwrap_or(UNKNOWN_HOST),
+            String::from_utf8_lossy(cwd.as_bytes()),
+        )),
+        prompt_char,
+    );
+}
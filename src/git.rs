@@ -1,105 +1,145 @@
+use super::cwd;
+use super::prefetch;
+use super::profile;
+use super::timeout;
+use super::util;
 use osyris::bstring::BString;
 use osyris::eval::{Scope, StackTrace, ValRef};
 use std::cell::RefCell;
-use std::env;
 use std::fs;
 use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
 use std::rc::Rc;
 
+// Everything the lazies need, gathered in one go on another thread
+struct GitInfo {
+    gitdir: PathBuf,
+    workdir: PathBuf,
+
+    // The cwd the repo was found from, logical or physical
+    cwd: PathBuf,
+    head: Option<Vec<u8>>,
+}
+
 struct GitCtx {
-    has_searched_gitdir: bool,
-    gitdir: Option<PathBuf>,
-    workdir: Option<PathBuf>,
+    info: Option<Option<GitInfo>>,
+
+    // Set by (prefetch 'git)
+    pending: Option<timeout::Pending<Option<GitInfo>>>,
 }
 
-impl GitCtx {
-    fn new() -> Self {
-        Self {
-            has_searched_gitdir: false,
-            gitdir: None,
-            workdir: None,
-        }
-    }
+fn find_gitdir_from_file(mut path: PathBuf) -> Option<PathBuf> {
+    let f = match fs::File::open(&path) {
+        Err(..) => return None,
+        Ok(f) => f,
+    };
 
-    fn find_gitdir_from_file(&mut self, mut path: PathBuf) -> Option<PathBuf> {
-        let f = match fs::File::open(&path) {
+    let content = match BufReader::new(f).lines().next() {
+        None => return None,
+        Some(content) => match content {
             Err(..) => return None,
-            Ok(f) => f,
-        };
+            Ok(content) => content,
+        },
+    };
 
-        let content = match BufReader::new(f).lines().next() {
-            None => return None,
-            Some(content) => match content {
-                Err(..) => return None,
-                Ok(content) => content,
-            },
-        };
+    if let Some(p) = content.strip_prefix("gitdir: ") {
+        path.pop(); // Remove the .git component of the parent dir
+        return Some(path.join(p));
+    }
+
+    None
+}
 
-        if let Some(p) = content.strip_prefix("gitdir: ") {
-            path.pop(); // Remove the .git component of the parent dir
-            return Some(path.join(p));
+// Returns the gitdir and the workdir
+fn search_gitdir(cwd: PathBuf) -> Option<(PathBuf, PathBuf)> {
+    let mut vec = Vec::from_iter(cwd.components());
+    while vec.len() > 0 {
+        let mut path = PathBuf::new();
+        for c in &vec {
+            path.push(c)
         }
 
-        None
+        path.push(".git");
+        match fs::metadata(&path) {
+            Ok(meta) => {
+                if meta.is_dir() {
+                    // Pretend we don't have a .git dir if it's invalid UTF-8,
+                    // for simplicity
+                    match path.to_str() {
+                        None => return None,
+                        Some(..) => {
+                            let gitdir = path.clone();
+                            path.pop();
+                            return Some((gitdir, path));
+                        }
+                    };
+                } else if meta.is_file() {
+                    // Worktrees and submodules have a .git file,
+                    // the workdir is the directory containing it
+                    let gitdir = find_gitdir_from_file(path.clone())?;
+                    path.pop();
+                    return Some((gitdir, path));
+                }
+            }
+            Err(..) => (),
+        };
+
+        vec.pop();
     }
 
-    fn find_gitdir(&mut self) -> bool {
-        if self.has_searched_gitdir {
-            return self.gitdir.is_some();
+    None
+}
+
+fn gather(starts: Vec<PathBuf>) -> Option<GitInfo> {
+    let (cwd, (gitdir, workdir)) = starts
+        .into_iter()
+        .find_map(|cwd| Some((cwd.clone(), search_gitdir(cwd)?)))?;
+    let head = util::read_first_line(&gitdir.join("HEAD"));
+    Some(GitInfo {
+        gitdir,
+        workdir,
+        cwd,
+        head,
+    })
+}
+
+impl GitCtx {
+    fn new() -> Self {
+        Self {
+            info: None,
+            pending: None,
         }
+    }
 
-        let cwd = match env::current_dir() {
-            Ok(dir) => dir,
-            Err(..) => {
-                return false;
-            }
-        };
+    fn prefetch(&mut self) {
+        if self.info.is_some() || self.pending.is_some() {
+            return;
+        }
 
-        let mut vec = Vec::from_iter(cwd.components());
-        while vec.len() > 0 {
-            let mut path = PathBuf::new();
-            for c in &vec {
-                path.push(c)
-            }
+        let starts = cwd::search_starts();
+        self.pending = Some(timeout::Pending::spawn(move || gather(starts)));
+    }
 
-            path.push(".git");
-            match fs::metadata(&path) {
-                Ok(meta) => {
-                    if meta.is_dir() {
-                        self.has_searched_gitdir = true;
-
-                        // Pretend we don't have a .git dir if it's invalid UTF-8,
-                        // for simplicity
-                        match path.to_str() {
-                            None => return false,
-                            Some(..) => {
-                                self.gitdir = Some(path.clone());
-                                path.pop();
-                                self.workdir = Some(path);
-                                return true;
-                            }
-                        };
-                    } else if meta.is_file() {
-                        self.has_searched_gitdir = true;
-                        self.gitdir = self.find_gitdir_from_file(path);
-                        self.workdir = self.gitdir.clone();
-                        return self.gitdir.is_some();
-                    }
+    // A repo which can't be read before the deadline
+    // is treated as not being in a repo
+    fn info(&mut self) -> Option<&GitInfo> {
+        if self.info.is_none() {
+            let info = match self.pending.take() {
+                Some(pending) => pending.wait().flatten(),
+                None => {
+                    let starts = cwd::search_starts();
+                    timeout::run(move || gather(starts)).flatten()
                 }
-                Err(..) => (),
             };
-
-            vec.pop();
+            self.info = Some(info);
         }
 
-        self.has_searched_gitdir = true;
-        false
+        self.info.as_ref().unwrap().as_ref()
     }
 }
 
 fn has_git(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
-    if ctx.borrow_mut().find_gitdir() {
+    if ctx.borrow_mut().info().is_some() {
         Ok(ValRef::Bool(true))
     } else {
         Ok(ValRef::Bool(false))
@@ -107,53 +147,64 @@ fn has_git(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
 }
 
 fn git_dir(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
-    ctx.borrow_mut().find_gitdir();
-    match &ctx.borrow().gitdir {
-        Some(dir) => {
-            Ok(ValRef::String(Rc::new(BString::from_os_str(dir.as_os_str()))))
-        }
+    match ctx.borrow_mut().info() {
+        Some(info) => Ok(ValRef::String(Rc::new(BString::from_os_str(
+            info.gitdir.as_os_str(),
+        )))),
         None => Ok(ValRef::None),
     }
 }
 
 fn git_workdir(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
-    ctx.borrow_mut().find_gitdir();
-    match &ctx.borrow().workdir {
-        Some(dir) => {
-            Ok(ValRef::String(Rc::new(BString::from_os_str(dir.as_os_str()))))
-        }
+    match ctx.borrow_mut().info() {
+        Some(info) => Ok(ValRef::String(Rc::new(BString::from_os_str(
+            info.workdir.as_os_str(),
+        )))),
         None => Ok(ValRef::None),
     }
 }
 
 fn git_branch(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
-    ctx.borrow_mut().find_gitdir();
-    let mut path = match &ctx.borrow().gitdir {
-        None => return Ok(ValRef::None),
-        Some(path) => path.clone(),
+    let mut ctx = ctx.borrow_mut();
+    let content = match ctx.info() {
+        Some(GitInfo {
+            head: Some(head), ..
+        }) => head,
+        _ => return Ok(ValRef::None),
     };
 
-    path.push("HEAD");
-    let f = match fs::File::open(&path) {
-        Err(..) => return Ok(ValRef::None),
-        Ok(f) => f,
+    let branch = if let Some(branch) = content.strip_prefix(b"ref: refs/heads/") {
+        branch
+    } else {
+        content.get(..8).unwrap_or(content)
     };
 
-    let content = match BufReader::new(f).split(b'\n').next() {
+    return Ok(ValRef::String(Rc::new(BString::from_bytes(branch))));
+}
+
+// The path of the cwd relative to the workdir, prefixed with the name of the repo,
+// like 'starstruck/src/sys'
+fn repo_relative(ctx: &Rc<RefCell<GitCtx>>) -> Result<ValRef, StackTrace> {
+    let (workdir, cwd) = match ctx.borrow_mut().info() {
         None => return Ok(ValRef::None),
-        Some(content) => match content {
-            Err(..) => return Ok(ValRef::None),
-            Ok(content) => content,
-        },
+        Some(info) => (info.workdir.clone(), info.cwd.clone()),
     };
 
-    let branch = if let Some(branch) = content.strip_prefix(b"ref: refs/heads/") {
-        branch
-    } else {
-        &content[..8]
+    let relative = match cwd.strip_prefix(&workdir) {
+        Ok(relative) => relative,
+        Err(..) => return Ok(ValRef::None),
     };
 
-    return Ok(ValRef::String(Rc::new(BString::from_bytes(branch))));
+    let mut path = match workdir.file_name() {
+        Some(name) => PathBuf::from(name),
+        None => PathBuf::from("/"),
+    };
+    path.push(relative);
+
+    // Don't end up with 'starstruck/' in the repo root
+    Ok(ValRef::String(Rc::new(BString::from_os_str(
+        path.components().as_path().as_os_str(),
+    ))))
 }
 
 pub fn init(mut scope: Scope) -> Scope {
@@ -162,7 +213,10 @@ pub fn init(mut scope: Scope) -> Scope {
     macro_rules! put {
         ($name: expr, $func: expr) => {
             let c = ctx.clone();
-            scope = scope.put_lazy($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope))));
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
         };
     }
 
@@ -170,5 +224,51 @@ pub fn init(mut scope: Scope) -> Scope {
     put!("git-dir", git_dir);
     put!("git-workdir", git_workdir);
     put!("git-branch", git_branch);
+    put!("repo-relative", repo_relative);
+
+    for name in [
+        "git",
+        "has-git?",
+        "git-dir",
+        "git-workdir",
+        "git-branch",
+        "repo-relative",
+    ] {
+        let c = ctx.clone();
+        prefetch::register(name, Rc::new(move || c.borrow_mut().prefetch()));
+    }
+
     scope
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture;
+
+    #[test]
+    #[cfg(unix)]
+    fn gathers_from_logical_cwd_first() {
+        use std::os::unix::fs::symlink;
+
+        let dir = fixture(&[
+            ("repo/.git/HEAD", "ref: refs/heads/main\n"),
+            ("repo/src/x", ""),
+        ]);
+        symlink(dir.join("repo"), dir.join("link")).unwrap();
+        symlink(dir.join("repo/src"), dir.join("src-link")).unwrap();
+
+        // Through a link to the repo, the workdir is the link
+        let info = gather(vec![dir.join("link/src"), dir.join("repo/src")]).unwrap();
+        assert_eq!(info.workdir, dir.join("link"));
+        assert_eq!(info.cwd, dir.join("link/src"));
+        assert_eq!(info.head.as_deref(), Some(&b"ref: refs/heads/main"[..]));
+
+        // A link into the middle of the repo falls back to the physical cwd
+        let info = gather(vec![dir.join("src-link"), dir.join("repo/src")]).unwrap();
+        assert_eq!(info.workdir, dir.join("repo"));
+        assert_eq!(info.cwd, dir.join("repo/src"));
+
+        assert!(gather(vec![dir.clone()]).is_none());
+    }
+}
@@ -0,0 +1,143 @@
+use super::profile;
+use super::sys;
+use osyris::bstring::BString;
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::rc::Rc;
+
+struct HostCtx {
+    aliases: HashMap<String, String>,
+}
+
+fn short_name(name: &str) -> &str {
+    match name.split_once('.') {
+        Some((short, _)) => short,
+        None => name,
+    }
+}
+
+// Most systems only have the short name as their hostname. Without doing
+// DNS lookups (far too slow for a prompt), the best source for the domain
+// is /etc/hosts, where the canonical name comes first on a line.
+fn fqdn(name: &str) -> String {
+    if name.contains('.') {
+        return name.to_string();
+    }
+
+    if let Ok(content) = fs::read_to_string("/etc/hosts") {
+        for line in content.lines() {
+            let line = match line.split_once('#') {
+                Some((line, _)) => line,
+                None => line,
+            };
+
+            let mut names = line.split_whitespace().skip(1);
+            let canonical = match names.next() {
+                Some(canonical) => canonical,
+                None => continue,
+            };
+
+            if short_name(canonical) == name && canonical.contains('.') {
+                return canonical.to_string();
+            }
+
+            if canonical.contains('.') && names.any(|n| n == name) {
+                return canonical.to_string();
+            }
+        }
+    }
+
+    name.to_string()
+}
+
+impl HostCtx {
+    fn new() -> Self {
+        Self {
+            aliases: HashMap::new(),
+        }
+    }
+
+    fn alias(&self, name: &str) -> Option<String> {
+        self.aliases
+            .get(name)
+            .or_else(|| self.aliases.get(short_name(name)))
+            .cloned()
+    }
+}
+
+// The host lazies are none if the hostname can't be found
+fn host(ctx: &Rc<RefCell<HostCtx>>) -> Result<ValRef, StackTrace> {
+    let name = match sys::hostname() {
+        Some(name) => name,
+        None => return Ok(ValRef::None),
+    };
+    let name = ctx.borrow().alias(&name).unwrap_or(name);
+    Ok(ValRef::String(Rc::new(BString::from_string(name))))
+}
+
+fn host_short(ctx: &Rc<RefCell<HostCtx>>) -> Result<ValRef, StackTrace> {
+    let name = match sys::hostname() {
+        Some(name) => name,
+        None => return Ok(ValRef::None),
+    };
+    let name = match ctx.borrow().alias(&name) {
+        Some(alias) => alias,
+        None => short_name(&name).to_string(),
+    };
+    Ok(ValRef::String(Rc::new(BString::from_string(name))))
+}
+
+fn host_fqdn(_: &Rc<RefCell<HostCtx>>) -> Result<ValRef, StackTrace> {
+    match sys::hostname() {
+        Some(name) => Ok(ValRef::String(Rc::new(BString::from_string(fqdn(&name))))),
+        None => Ok(ValRef::None),
+    }
+}
+
+fn host_alias(ctx: &Rc<RefCell<HostCtx>>, args: Vec<ValRef>) -> Result<ValRef, StackTrace> {
+    if args.len() != 2 {
+        return Err(StackTrace::from_str("'host-alias' requires 2 arguments"));
+    }
+
+    let (name, alias) = match (&args[0], &args[1]) {
+        (ValRef::String(name), ValRef::String(alias)) => (name, alias),
+        _ => {
+            return Err(StackTrace::from_str(
+                "'host-alias' requires string arguments",
+            ))
+        }
+    };
+
+    ctx.borrow_mut().aliases.insert(
+        String::from_utf8_lossy(name.as_bytes()).to_string(),
+        String::from_utf8_lossy(alias.as_bytes()).to_string(),
+    );
+    Ok(ValRef::None)
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(HostCtx::new()));
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
+        };
+    }
+
+    put!("host", host);
+    put!("host-short", host_short);
+    put!("host-fqdn", host_fqdn);
+
+    scope = scope.put_func(
+        "host-alias",
+        Rc::new(move |args, scope| Ok((host_alias(&ctx, args)?, scope))),
+    );
+
+    scope
+}
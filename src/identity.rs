@@ -0,0 +1,71 @@
+use super::profile;
+use super::sys;
+use super::util::non_empty_var;
+use osyris::bstring::BString;
+use osyris::eval::{FuncResult, Scope, ValRef};
+use std::cell::RefCell;
+use std::rc::Rc;
+
+fn id_or_none(id: Option<u32>) -> ValRef {
+    match id {
+        Some(id) => ValRef::Number(id as f64),
+        None => ValRef::None,
+    }
+}
+
+fn uid(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((id_or_none(sys::uid()), scope))
+}
+
+fn euid(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((id_or_none(sys::euid()), scope))
+}
+
+fn groups(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let groups = sys::groups()
+        .into_iter()
+        .map(|gid| {
+            let name = sys::group_name(gid).unwrap_or_else(|| format!("{}", gid));
+            ValRef::String(Rc::new(BString::from_string(name)))
+        })
+        .collect();
+
+    Ok((ValRef::List(Rc::new(RefCell::new(groups))), scope))
+}
+
+fn is_root(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    Ok((ValRef::Bool(sys::euid() == Some(0)), scope))
+}
+
+fn sudo_user(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    match non_empty_var("SUDO_USER") {
+        Some(user) => Ok((ValRef::String(Rc::new(BString::from_string(user))), scope)),
+        None => Ok((ValRef::None, scope)),
+    }
+}
+
+// The user who originally logged in, even after 'sudo' or 'su'.
+// getlogin() fails without a controlling terminal; sudo sets SUDO_USER,
+// and login, sshd and cron all set LOGNAME.
+fn login_name(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let name = sys::login_name()
+        .or_else(|| non_empty_var("SUDO_USER"))
+        .or_else(|| non_empty_var("LOGNAME"))
+        .or_else(|| non_empty_var("USER"))
+        .unwrap_or_else(sys::username);
+
+    Ok((ValRef::String(Rc::new(BString::from_string(name))), scope))
+}
+
+pub fn init(mut s: Scope) -> Scope {
+    s = s.put_lazy("uid", profile::wrap("uid", Rc::new(uid)));
+    s = s.put_lazy("euid", profile::wrap("euid", Rc::new(euid)));
+    s = s.put_lazy("groups", profile::wrap("groups", Rc::new(groups)));
+    s = s.put_lazy("is-root?", profile::wrap("is-root?", Rc::new(is_root)));
+    s = s.put_lazy("sudo-user", profile::wrap("sudo-user", Rc::new(sudo_user)));
+    s = s.put_lazy(
+        "login-name",
+        profile::wrap("login-name", Rc::new(login_name)),
+    );
+    s
+}
@@ -0,0 +1,310 @@
+use super::source::{self, TokenKind};
+use osyris::bstring::BString;
+use osyris::eval::{FuncResult, Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Called with the source of each file before it runs, to change the scope
+// it runs in; --check uses this to stub out the lazies the file refers to
+pub type Prepare = fn(&str, Scope) -> Scope;
+
+struct ImportCtx {
+    // The files which are currently being executed, innermost last.
+    // Relative paths are resolved against the innermost one,
+    // and a file which is already on the stack is an import cycle.
+    stack: Vec<PathBuf>,
+
+    // The values of imported modules, by canonical path
+    cache: HashMap<PathBuf, ValRef>,
+
+    prepare: Option<Prepare>,
+}
+
+impl ImportCtx {
+    fn new(config_path: Option<&Path>, prepare: Option<Prepare>) -> Self {
+        let mut stack = Vec::new();
+        if let Some(path) = config_path {
+            stack.push(fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf()));
+        }
+
+        Self {
+            stack,
+            cache: HashMap::new(),
+            prepare,
+        }
+    }
+
+    // The importing file, and the position of the call in it, like
+    // 'main.lsp:3:1'. Osyris doesn't keep positions, so the call is found
+    // by reading the importer again, which only happens for errors.
+    fn importer(&self, name: &str, arg: &BString) -> String {
+        let (importer, src) = match self.stack.last() {
+            Some(path) => (path.display().to_string(), fs::read_to_string(path).ok()),
+            None => (
+                "<default config>".to_string(),
+                Some(super::DEFAULT_CONFIG.to_string()),
+            ),
+        };
+
+        match src.and_then(|src| find_call(&src, name, arg)) {
+            Some(pos) => format!("{}:{}:{}", importer, pos.line, pos.col),
+            None => importer,
+        }
+    }
+
+    // The built-in config has no file, so it imports relative to the cwd
+    fn resolve(&self, name: &BString) -> PathBuf {
+        let path = PathBuf::from(name.to_os_str());
+        match self.stack.last().and_then(|file| file.parent()) {
+            Some(dir) => dir.join(path),
+            None => match env::current_dir() {
+                Ok(dir) => dir.join(path),
+                Err(..) => path,
+            },
+        }
+    }
+}
+
+// Finds the first '(name "arg"' in the source
+fn find_call(src: &str, name: &str, arg: &BString) -> Option<source::Pos> {
+    let arg = format!("\"{}\"", arg);
+    source::scan(src)
+        .windows(3)
+        .find(|w| {
+            w[0].text == "("
+                && (w[1].kind, w[1].text) == (TokenKind::Ident, name)
+                && (w[2].kind, w[2].text) == (TokenKind::String, arg.as_str())
+        })
+        .map(|w| w[0].pos)
+}
+
+fn path_arg(name: &str, args: &[ValRef]) -> Result<Rc<BString>, StackTrace> {
+    if args.len() != 1 {
+        return Err(StackTrace::from_string(format!(
+            "'{}' requires 1 argument",
+            name
+        )));
+    }
+
+    match &args[0] {
+        ValRef::String(s) => Ok(s.clone()),
+        _ => Err(StackTrace::from_string(format!(
+            "'{}' requires a string argument",
+            name
+        ))),
+    }
+}
+
+// Runs a file in the given scope, with the file on the import stack
+// for the duration. Errors are prefixed with the importing file and
+// the position of the call, so that an error deep in a chain of imports
+// can be traced back.
+fn run(
+    ctx: &Rc<RefCell<ImportCtx>>,
+    name: &str,
+    args: Vec<ValRef>,
+    scope: Scope,
+) -> Result<(PathBuf, ValRef, Scope), StackTrace> {
+    let arg = path_arg(name, &args)?;
+    let importer = || ctx.borrow().importer(name, &arg);
+    let path = ctx.borrow().resolve(&arg);
+
+    let path = match fs::canonicalize(&path) {
+        Ok(path) => path,
+        Err(err) => {
+            return Err(StackTrace::from_string(format!(
+                "{}: {} {:?}: {}",
+                importer(),
+                name,
+                arg.to_os_str(),
+                err
+            )))
+        }
+    };
+
+    if ctx.borrow().stack.contains(&path) {
+        let ctx = ctx.borrow();
+        let start = ctx.stack.iter().position(|p| p == &path).unwrap();
+        let mut chain: Vec<String> = ctx.stack[start..]
+            .iter()
+            .map(|p| p.display().to_string())
+            .collect();
+        chain.push(path.display().to_string());
+        return Err(StackTrace::from_string(format!(
+            "{}: Import cycle: {}",
+            importer(),
+            chain.join(" -> ")
+        )));
+    }
+
+    let content = match fs::read(&path) {
+        Ok(content) => content,
+        Err(err) => {
+            return Err(StackTrace::from_string(format!(
+                "{}: {} {:?}: {}",
+                importer(),
+                name,
+                path,
+                err
+            )))
+        }
+    };
+
+    let content = String::from_utf8_lossy(&content);
+    let prepare = ctx.borrow().prepare;
+    let scope = match prepare {
+        Some(prepare) => prepare(&content, scope),
+        None => scope,
+    };
+
+    ctx.borrow_mut().stack.push(path.clone());
+    let res = super::execute_file(&path.display().to_string(), &content, scope);
+    ctx.borrow_mut().stack.pop();
+
+    match res {
+        Ok((val, scope)) => Ok((path, val, scope)),
+        Err(err) => Err(StackTrace::from_string(format!(
+            "{}: {} {:?}: {}",
+            importer(),
+            name,
+            path,
+            err
+        ))),
+    }
+}
+
+// (import path)
+// Runs the file once and returns its last value, like a dict of segments.
+// Definitions made by the file stay in the file.
+fn import(ctx: &Rc<RefCell<ImportCtx>>, args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let arg = path_arg("import", &args)?;
+    let cached = fs::canonicalize(ctx.borrow().resolve(&arg))
+        .ok()
+        .and_then(|path| ctx.borrow().cache.get(&path).cloned());
+    if let Some(val) = cached {
+        return Ok((val, scope));
+    }
+
+    let (path, val, _) = run(ctx, "import", args, scope.clone())?;
+    ctx.borrow_mut().cache.insert(path, val.clone());
+    Ok((val, scope))
+}
+
+// (include path)
+// Runs the file every time, in the scope of the includer,
+// so the file's definitions become visible to the includer.
+fn include(ctx: &Rc<RefCell<ImportCtx>>, args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let (_, val, scope) = run(ctx, "include", args, scope)?;
+    Ok((val, scope))
+}
+
+pub fn init(scope: Scope, config_path: Option<&Path>) -> Scope {
+    init_with(scope, config_path, None)
+}
+
+pub fn init_with(mut scope: Scope, config_path: Option<&Path>, prepare: Option<Prepare>) -> Scope {
+    let ctx = Rc::new(RefCell::new(ImportCtx::new(config_path, prepare)));
+
+    let c = ctx.clone();
+    scope = scope.put_func(
+        "import",
+        Rc::new(move |args, scope| import(&c, args, scope)),
+    );
+    scope = scope.put_func(
+        "include",
+        Rc::new(move |args, scope| include(&ctx, args, scope)),
+    );
+
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture;
+
+    fn ctx(importer: &Path) -> Rc<RefCell<ImportCtx>> {
+        Rc::new(RefCell::new(ImportCtx::new(Some(importer), None)))
+    }
+
+    fn call(ctx: &Rc<RefCell<ImportCtx>>, path: &str) -> Result<ValRef, String> {
+        let args = vec![ValRef::String(Rc::new(BString::from_str(path)))];
+        match import(ctx, args, Scope::new()) {
+            Ok((val, _)) => Ok(val),
+            Err(err) => Err(err.to_string()),
+        }
+    }
+
+    fn call_err(ctx: &Rc<RefCell<ImportCtx>>, path: &str) -> String {
+        match call(ctx, path) {
+            Ok(..) => panic!("importing {:?} didn't fail", path),
+            Err(err) => err,
+        }
+    }
+
+    #[test]
+    fn finds_calls() {
+        let src = "; (import \"a.lsp\")\n(def 'x (import \"b.lsp\"))\n  (import \"a.lsp\")";
+        let pos = |name| find_call(src, "import", &BString::from_str(name));
+        assert_eq!(pos("a.lsp").map(|p| (p.line, p.col)), Some((3, 3)));
+        assert_eq!(pos("b.lsp").map(|p| (p.line, p.col)), Some((2, 9)));
+        assert_eq!(pos("c.lsp"), None);
+    }
+
+    #[test]
+    fn self_import() {
+        let dir = fixture(&[("a.lsp", "; a\n(import \"a.lsp\")\n")]);
+        let a = fs::canonicalize(dir.join("a.lsp")).unwrap();
+        let err = call_err(&ctx(&a), "a.lsp");
+        assert!(err.contains(&format!("{}:2:1: Import cycle", a.display())));
+        assert!(err.ends_with(&format!("{} -> {}", a.display(), a.display())));
+    }
+
+    #[test]
+    fn import_cycle() {
+        let dir = fixture(&[
+            ("a.lsp", "(import \"b.lsp\")\n"),
+            ("b.lsp", "\n(import \"a.lsp\")\n"),
+        ]);
+        let a = fs::canonicalize(dir.join("a.lsp")).unwrap();
+        let b = fs::canonicalize(dir.join("b.lsp")).unwrap();
+
+        // As it is while a.lsp is importing b.lsp
+        let ctx = ctx(&a);
+        ctx.borrow_mut().stack.push(b.clone());
+        let err = call_err(&ctx, "a.lsp");
+        assert!(err.contains(&format!("{}:2:1: Import cycle", b.display())));
+        assert!(err.ends_with(&format!(
+            "{} -> {} -> {}",
+            a.display(),
+            b.display(),
+            a.display()
+        )));
+    }
+
+    #[test]
+    fn missing_file() {
+        let dir = fixture(&[("main.lsp", "(import \"nope.lsp\")")]);
+        let err = call_err(&ctx(&dir.join("main.lsp")), "nope.lsp");
+        assert!(err.starts_with(&format!("{}:1:1: import", dir.join("main.lsp").display())));
+    }
+
+    #[test]
+    fn cache_by_canonical_path() {
+        let dir = fixture(&[("main.lsp", ""), ("lib.lsp", ""), ("sub/other.lsp", "")]);
+        let ctx = ctx(&dir.join("main.lsp"));
+        call(&ctx, "lib.lsp").unwrap();
+        assert_eq!(ctx.borrow().cache.len(), 1);
+
+        // A cached value is returned without running the file again
+        let lib = fs::canonicalize(dir.join("lib.lsp")).unwrap();
+        ctx.borrow_mut().cache.insert(lib, ValRef::Number(42.0));
+        let val = call(&ctx, "sub/../lib.lsp").unwrap();
+        assert!(matches!(val, ValRef::Number(n) if n == 42.0));
+        assert_eq!(ctx.borrow().cache.len(), 1);
+    }
+}
@@ -0,0 +1,403 @@
+use super::profile;
+use super::timeout;
+use super::util::{string_or_none, unquote};
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// A tiny subset of YAML: block mappings, block sequences and scalars.
+// That's all kubectl writes to kubeconfig files, and pulling in a full
+// YAML parser would cost more startup time than the prompt can afford.
+enum Node {
+    Scalar(String),
+    List(Vec<Node>),
+    Map(Vec<(String, Node)>),
+}
+
+impl Node {
+    fn get(&self, key: &str) -> Option<&Node> {
+        match self {
+            Node::Map(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    fn get_str(&self, key: &str) -> Option<&str> {
+        match self.get(key) {
+            Some(Node::Scalar(s)) if !s.is_empty() => Some(s),
+            _ => None,
+        }
+    }
+
+    fn items(&self) -> &[Node] {
+        match self {
+            Node::List(items) => items,
+            _ => &[],
+        }
+    }
+}
+
+struct Line {
+    indent: usize,
+    text: String,
+}
+
+impl Line {
+    fn is_list_item(&self) -> bool {
+        self.text == "-" || self.text.starts_with("- ")
+    }
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut prev = ' ';
+    for (idx, ch) in line.char_indices() {
+        match quote {
+            Some(q) if ch == q => quote = None,
+            Some(..) => (),
+            None if ch == '"' || ch == '\'' => quote = Some(ch),
+            None if ch == '#' && prev.is_whitespace() => return &line[..idx],
+            None => (),
+        }
+        prev = ch;
+    }
+
+    line
+}
+
+fn split_lines(content: &str) -> Vec<Line> {
+    let mut lines = Vec::new();
+    for line in content.lines() {
+        let line = strip_comment(line).trim_end();
+        let text = line.trim_start();
+        if text.is_empty() || text == "---" {
+            continue;
+        }
+
+        lines.push(Line {
+            indent: line.len() - text.len(),
+            text: text.to_string(),
+        });
+    }
+
+    lines
+}
+
+fn parse_list(lines: &mut Vec<Line>, idx: &mut usize, indent: usize) -> Node {
+    let mut items = Vec::new();
+    while *idx < lines.len() && lines[*idx].indent == indent && lines[*idx].is_list_item() {
+        let rest = lines[*idx].text[1..].trim_start().to_string();
+        if rest.is_empty() {
+            *idx += 1;
+            match lines.get(*idx) {
+                Some(line) if line.indent > indent => {
+                    let child_indent = line.indent;
+                    items.push(parse_node(lines, idx, child_indent));
+                }
+                _ => items.push(Node::Scalar(String::new())),
+            }
+        } else {
+            // Treat "- key: val" as if "key: val" was on its own line,
+            // indented to where the content starts
+            let child_indent = indent + lines[*idx].text.len() - rest.len();
+            lines[*idx] = Line {
+                indent: child_indent,
+                text: rest,
+            };
+            items.push(parse_node(lines, idx, child_indent));
+        }
+    }
+
+    Node::List(items)
+}
+
+fn parse_map(lines: &mut Vec<Line>, idx: &mut usize, indent: usize) -> Node {
+    let mut entries = Vec::new();
+    while *idx < lines.len() && lines[*idx].indent == indent && !lines[*idx].is_list_item() {
+        let text = lines[*idx].text.clone();
+        *idx += 1;
+
+        let (key, val) = match text.split_once(": ") {
+            Some((key, val)) => (key, val),
+            None => match text.strip_suffix(':') {
+                Some(key) => (key, ""),
+                None => continue,
+            },
+        };
+
+        let key = unquote(key).to_string();
+        if !val.trim().is_empty() {
+            entries.push((key, Node::Scalar(unquote(val).to_string())));
+            continue;
+        }
+
+        // YAML allows a sequence to be indented at the same level as its key
+        let child = match lines.get(*idx) {
+            Some(line) if line.indent > indent => {
+                let child_indent = line.indent;
+                parse_node(lines, idx, child_indent)
+            }
+            Some(line) if line.indent == indent && line.is_list_item() => {
+                parse_list(lines, idx, indent)
+            }
+            _ => Node::Scalar(String::new()),
+        };
+        entries.push((key, child));
+    }
+
+    Node::Map(entries)
+}
+
+fn parse_node(lines: &mut Vec<Line>, idx: &mut usize, indent: usize) -> Node {
+    let line = &lines[*idx];
+    if line.is_list_item() {
+        parse_list(lines, idx, indent)
+    } else if line.text.contains(": ") || line.text.ends_with(':') {
+        parse_map(lines, idx, indent)
+    } else {
+        *idx += 1;
+        Node::Scalar(unquote(&lines[*idx - 1].text).to_string())
+    }
+}
+
+fn parse_yaml(content: &str) -> Option<Node> {
+    let mut lines = split_lines(content);
+    if lines.is_empty() {
+        return None;
+    }
+
+    let mut idx = 0;
+    let indent = lines[0].indent;
+    Some(parse_node(&mut lines, &mut idx, indent))
+}
+
+struct KubeContext {
+    name: String,
+    cluster: Option<String>,
+    namespace: Option<String>,
+}
+
+struct KubeCtx {
+    has_loaded: bool,
+    context: Option<KubeContext>,
+}
+
+impl KubeCtx {
+    fn new() -> Self {
+        Self {
+            has_loaded: false,
+            context: None,
+        }
+    }
+
+    fn config_paths() -> Vec<PathBuf> {
+        if let Some(paths) = env::var_os("KUBECONFIG") {
+            if !paths.is_empty() {
+                return env::split_paths(&paths).collect();
+            }
+        }
+
+        match dirs::home_dir() {
+            Some(mut path) => {
+                path.push(".kube");
+                path.push("config");
+                vec![path]
+            }
+            None => Vec::new(),
+        }
+    }
+
+    fn load(&mut self) {
+        if self.has_loaded {
+            return;
+        }
+        self.has_loaded = true;
+
+        let paths = Self::config_paths();
+        let contents = timeout::run(move || {
+            paths
+                .iter()
+                .filter_map(|path| fs::read_to_string(path).ok())
+                .collect::<Vec<_>>()
+        });
+
+        let docs: Vec<Node> = contents
+            .unwrap_or_default()
+            .iter()
+            .filter_map(|content| parse_yaml(content))
+            .collect();
+        self.context = find_context(&docs);
+    }
+}
+
+// kubectl merges the files in KUBECONFIG; the first file to set
+// 'current-context' wins, and so does the first context with a given name
+fn find_context(docs: &[Node]) -> Option<KubeContext> {
+    let current = docs.iter().find_map(|doc| doc.get_str("current-context"))?;
+    let mut context = KubeContext {
+        name: current.to_string(),
+        cluster: None,
+        namespace: None,
+    };
+
+    let found = docs
+        .iter()
+        .filter_map(|doc| doc.get("contexts"))
+        .flat_map(|contexts| contexts.items())
+        .find(|item| item.get_str("name") == Some(current))
+        .and_then(|item| item.get("context"));
+    if let Some(found) = found {
+        context.cluster = found.get_str("cluster").map(|s| s.to_string());
+        context.namespace = found.get_str("namespace").map(|s| s.to_string());
+    }
+
+    Some(context)
+}
+
+fn kube_context(ctx: &Rc<RefCell<KubeCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().load();
+    let c = ctx.borrow();
+    Ok(string_or_none(c.context.as_ref().map(|c| c.name.as_str())))
+}
+
+fn kube_namespace(ctx: &Rc<RefCell<KubeCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().load();
+    let c = ctx.borrow();
+
+    // Like kubectl, a context without a namespace uses the 'default' namespace
+    Ok(string_or_none(c.context.as_ref().map(
+        |c| match &c.namespace {
+            Some(ns) => ns.as_str(),
+            None => "default",
+        },
+    )))
+}
+
+fn kube_cluster(ctx: &Rc<RefCell<KubeCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().load();
+    let c = ctx.borrow();
+    Ok(string_or_none(
+        c.context.as_ref().and_then(|c| c.cluster.as_deref()),
+    ))
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(KubeCtx::new()));
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
+        };
+    }
+
+    put!("kube-context", kube_context);
+    put!("kube-namespace", kube_namespace);
+    put!("kube-cluster", kube_cluster);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_kubeconfig() {
+        let doc = parse_yaml(
+            "apiVersion: v1\n\
+             kind: Config\n\
+             current-context: \"dev\" # comment\n\
+             contexts:\n\
+             - name: dev\n  \
+               context:\n    \
+                 cluster: dev-cluster\n    \
+                 namespace: 'team#1'\n\
+             - context:\n    \
+                 cluster: prod-cluster\n  \
+               name: prod\n",
+        )
+        .unwrap();
+
+        assert_eq!(doc.get_str("apiVersion"), Some("v1"));
+        assert_eq!(doc.get_str("kind"), Some("Config"));
+        assert_eq!(doc.get_str("current-context"), Some("dev"));
+
+        let contexts = doc.get("contexts").unwrap().items();
+        assert_eq!(contexts.len(), 2);
+        assert_eq!(contexts[0].get_str("name"), Some("dev"));
+        let dev = contexts[0].get("context").unwrap();
+        assert_eq!(dev.get_str("cluster"), Some("dev-cluster"));
+        assert_eq!(dev.get_str("namespace"), Some("team#1"));
+        assert_eq!(contexts[1].get_str("name"), Some("prod"));
+        let prod = contexts[1].get("context").unwrap();
+        assert_eq!(prod.get_str("cluster"), Some("prod-cluster"));
+    }
+
+    #[test]
+    fn parses_indented_sequences() {
+        let doc = parse_yaml(
+            "contexts:\n  \
+               - name: a\n  \
+               - name: b\n\
+             users:\n\
+             - name: u\n",
+        )
+        .unwrap();
+
+        let names: Vec<_> = doc
+            .get("contexts")
+            .unwrap()
+            .items()
+            .iter()
+            .map(|item| item.get_str("name"))
+            .collect();
+        assert_eq!(names, [Some("a"), Some("b")]);
+        assert_eq!(doc.get("users").unwrap().items().len(), 1);
+    }
+
+    #[test]
+    fn empty_config() {
+        assert!(parse_yaml("").is_none());
+        assert!(parse_yaml("# only a comment\n---\n").is_none());
+    }
+
+    #[test]
+    fn merges_multiple_files() {
+        // The first file only has contexts, the second sets the current context
+        // and has a context with the same name, which is shadowed by the first
+        let first = parse_yaml(
+            "contexts:\n\
+             - name: staging\n  \
+               context:\n    \
+                 cluster: first-cluster\n",
+        )
+        .unwrap();
+        let second = parse_yaml(
+            "current-context: staging\n\
+             contexts:\n\
+             - name: staging\n  \
+               context:\n    \
+                 cluster: second-cluster\n    \
+                 namespace: ignored\n",
+        )
+        .unwrap();
+        let third = parse_yaml("current-context: other\n").unwrap();
+
+        let context = find_context(&[first, second, third]).unwrap();
+        assert_eq!(context.name, "staging");
+        assert_eq!(context.cluster.as_deref(), Some("first-cluster"));
+        assert_eq!(context.namespace, None);
+    }
+
+    #[test]
+    fn no_current_context() {
+        let doc = parse_yaml("contexts:\n- name: a\n").unwrap();
+        assert!(find_context(&[doc]).is_none());
+    }
+}
@@ -1,8 +1,28 @@
 mod basic;
+mod check;
+mod clock;
+mod cloud;
 mod color;
+mod cwd;
+mod fallback;
 mod git;
+mod host;
+mod identity;
+mod import;
+mod kube;
+mod path;
+mod prefetch;
+mod profile;
+mod project;
+mod python;
+mod remote;
+mod source;
 mod state;
 mod sys;
+mod sysinfo;
+mod timeout;
+mod toolchain;
+mod util;
 
 use dirs;
 use eval::{Scope, StackTrace, ValRef};
@@ -10,10 +30,16 @@ use osyris::bstring::BString;
 use osyris::{eval, iolib, parse, stdlib};
 use std::cell::RefCell;
 use std::env;
+use std::ffi::OsString;
 use std::fs;
 use std::path::{Path, PathBuf};
 use std::process;
 use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+// Used when there's no config file, and printed by --print-default-config
+// so that users have something to start from
+const DEFAULT_CONFIG: &str = include_str!("../starstruck.example.lsp");
 
 pub struct UncountedString {
     pub s: String,
@@ -22,10 +48,14 @@ pub struct UncountedString {
 struct Printer {
     column: i32,
     row: i32,
+
+    // Nothing is written until the whole prompt has been rendered,
+    // so that an error doesn't leave half a prompt behind
+    output: String,
 }
 
 impl Printer {
-    fn print(&mut self, s: &String) {
+    fn print(&mut self, s: &str) {
         for ch in s.chars() {
             if ch == '\n' {
                 self.column = 1;
@@ -38,8 +68,8 @@ impl Printer {
         self.print_uncounted(s);
     }
 
-    fn print_uncounted(&self, s: &String) {
-        print!("{}", s)
+    fn print_uncounted(&mut self, s: &str) {
+        self.output.push_str(s);
     }
 }
 
@@ -62,10 +92,10 @@ fn print_ps1(
         }
         ValRef::String(s) => printer
             .borrow_mut()
-            .print(&String::from_utf8_lossy(s.as_bytes()).to_string()),
+            .print(&String::from_utf8_lossy(s.as_bytes())),
         ValRef::Native(n) => {
             if let Some(us) = n.as_ref().downcast_ref::<UncountedString>() {
-                printer.borrow().print_uncounted(&us.s);
+                printer.borrow_mut().print_uncounted(&us.s);
             }
         }
         ValRef::Port(..) => (),
@@ -84,32 +114,119 @@ fn print_ps1(
     Ok(scope)
 }
 
-fn execute_file(reader: &mut parse::Reader, mut scope: Scope) -> Result<ValRef, String> {
+// Runs a file, and returns its last value. Osyris doesn't keep positions
+// for the expressions it evaluates, so errors point at the start of
+// the top-level form which failed, as found by source::scan.
+fn execute_file(name: &str, src: &str, mut scope: Scope) -> Result<(ValRef, Scope), String> {
+    let mut reader = parse::Reader::new(src.as_bytes(), BString::from_str(name));
     let mut retval = ValRef::None;
+    let mut index = 0;
     loop {
-        let expr = match parse::parse(reader) {
-            Ok(expr) => match expr {
-                Some(expr) => expr,
-                None => return Ok(retval),
-            },
+        let expr = match parse::parse(&mut reader) {
+            Ok(Some(expr)) => expr,
+            Ok(None) => return Ok((retval, scope)),
             Err(err) => {
-                return Err(format!(
-                    "Parse error: {}:{}: {}",
-                    err.line, err.col, err.msg
+                return Err(source::format_error(
+                    name,
+                    src,
+                    err.line as usize,
+                    err.col as usize,
+                    &format!("Parse error: {}", err.msg),
                 ))
             }
         };
 
         match eval::eval(&expr, scope) {
-            Err(err) => return Err(format!("Error: {}", err)),
+            Err(err) => {
+                let msg = format!("Error: {}", err);
+                let tokens = source::scan(src);
+                return Err(match source::forms(&tokens).get(index) {
+                    Some(form) => {
+                        source::format_error(name, src, form.start.line, form.start.col, &msg)
+                    }
+                    None => format!("{}: {}", name, msg),
+                });
+            }
             Ok((val, s)) => {
                 retval = val;
                 scope = s;
             }
         }
+
+        index += 1;
     }
 }
 
+// Prints the value returned by the config. When the config ends with
+// a literal block, errors are located at the element which failed,
+// otherwise at the last form of the config. Osyris doesn't keep positions
+// for nested expressions, so that's as precise as it gets.
+fn print_config_ps1(
+    printer: &Rc<RefCell<Printer>>,
+    src: &str,
+    val: ValRef,
+    mut scope: Scope,
+    last_form: &source::Form,
+    elements: &[source::Form],
+) -> Result<Scope, (source::Pos, StackTrace)> {
+    match val {
+        ValRef::Block(exprs) if exprs.len() == elements.len() => {
+            for (expr, elem) in exprs.iter().zip(elements) {
+                let text = src[elem.start.offset..elem.end]
+                    .lines()
+                    .next()
+                    .unwrap_or("");
+                let name = format!(
+                    "segment {}:{} {}",
+                    elem.start.line,
+                    elem.start.col,
+                    text.chars().take(40).collect::<String>()
+                );
+                scope = profile::time(&name, || {
+                    let (val, s) = eval::eval(expr, scope)?;
+                    print_ps1(printer, val, s)
+                })
+                .map_err(|err| (elem.start, err))?;
+            }
+            Ok(scope)
+        }
+        val => print_ps1(printer, val, scope).map_err(|err| (last_form.start, err)),
+    }
+}
+
+// Gets the value of a name in the scope, by evaluating it
+fn lookup(name: &str, scope: &Scope) -> Result<ValRef, StackTrace> {
+    let mut reader = parse::Reader::new(name.as_bytes(), BString::from_str("<lookup>"));
+    let expr = match parse::parse(&mut reader) {
+        Ok(Some(expr)) => expr,
+        Ok(None) => return Err(StackTrace::from_str("Empty identifier")),
+        Err(err) => return Err(StackTrace::from_string(err.msg)),
+    };
+
+    let (val, _) = eval::eval(&expr, scope.clone())?;
+    Ok(val)
+}
+
+// Everything a config can use to build its segments.
+// Trusted project files get these too, but not iolib or import.
+// Modules register their prefetchers here, so this is only called once.
+fn init_segments(mut scope: Scope, state: &Rc<state::State>) -> Scope {
+    scope = basic::init(scope, state);
+    scope = cwd::init(scope);
+    scope = path::init(scope);
+    scope = identity::init(scope);
+    scope = host::init(scope);
+    scope = remote::init(scope);
+    scope = color::init(scope, state);
+    scope = git::init(scope);
+    scope = kube::init(scope);
+    scope = cloud::init(scope);
+    scope = python::init(scope);
+    scope = toolchain::init(scope);
+    scope = sysinfo::init(scope);
+    scope
+}
+
 fn find_config_path_from_base(base: PathBuf) -> Option<PathBuf> {
     let mut path = base.clone();
     path.push("starstruck");
@@ -127,17 +244,43 @@ fn find_config_path_from_base(base: PathBuf) -> Option<PathBuf> {
     None
 }
 
-fn find_config_path() -> Option<PathBuf> {
+// Returns the config path, and the reason it was chosen,
+// for --print-config-path
+fn find_config_path() -> Option<(PathBuf, String)> {
+    // An explicitly set path is used even if it doesn't exist,
+    // so that a typo results in an error rather than another config
+    if let Some(path) = env::var_os("STARSTRUCK_CONFIG") {
+        if !path.is_empty() {
+            return Some((PathBuf::from(path), "set by $STARSTRUCK_CONFIG".to_string()));
+        }
+    }
+
     if let Some(dir) = dirs::config_dir() {
         if let Some(path) = find_config_path_from_base(dir) {
-            return Some(path);
+            return Some((path, "found in the user config directory".to_string()));
         }
     }
 
     if let Some(mut dir) = dirs::home_dir() {
         dir.push(".config");
         if let Some(path) = find_config_path_from_base(dir) {
-            return Some(path);
+            return Some((path, "found in ~/.config".to_string()));
+        }
+    }
+
+    // System-wide configs, for admins to provide a default for all users.
+    // The XDG spec says that relative paths must be ignored.
+    let dirs = match env::var_os("XDG_CONFIG_DIRS") {
+        Some(dirs) if !dirs.is_empty() => dirs,
+        _ => OsString::from("/etc/xdg"),
+    };
+    for dir in env::split_paths(&dirs) {
+        if !dir.is_absolute() {
+            continue;
+        }
+
+        if let Some(path) = find_config_path_from_base(dir) {
+            return Some((path, "found in $XDG_CONFIG_DIRS".to_string()));
         }
     }
 
@@ -151,12 +294,26 @@ fn usage(argv0: String) {
     println!("  -h, --help: Show this help text");
     println!("  -c <path>:  Config file path");
     println!("  -e <code>:  Set the exit code of the previous command");
+    println!("  -t <ms>:    Give up on slow parts of the prompt after <ms> ms (default 1000, 0 for never)");
     println!("  --bash:     Set the shell to bash");
     println!("  --zsh:      Set the shell to zsh");
+    println!("  --print-default-config: Print the built-in config and exit");
+    println!("  --print-config-path: Print which config file would be used, and why");
+    println!("  --check:    Check the config for errors instead of printing a prompt");
+    println!("  --profile:  Print how long each part of the prompt took to stderr");
+    println!("  --allow:    Trust the .starstruck.lsp file for the current directory");
+    println!("  --deny:     Stop trusting the .starstruck.lsp file for the current directory");
 }
 
 fn main() {
-    let mut config_path = find_config_path();
+    let (mut config_path, mut config_reason) = match find_config_path() {
+        Some((path, reason)) => (Some(path), reason),
+        None => (None, "no config file found".to_string()),
+    };
+    let mut print_config_path = false;
+    let mut check_config = false;
+    let mut profile_config = false;
+    let mut timeout_ms = 1000;
 
     let mut state = state::State {
         exit_code: 0,
@@ -174,7 +331,10 @@ fn main() {
 
         if arg == "-c" {
             config_path = match args.next() {
-                Some(p) => Some(PathBuf::from(p)),
+                Some(p) => {
+                    config_reason = "set by the '-c' option".to_string();
+                    Some(PathBuf::from(p))
+                }
                 None => {
                     eprintln!("Option 'c' requires an argument");
                     process::exit(1);
@@ -194,10 +354,48 @@ fn main() {
                     process::exit(1);
                 }
             }
+        } else if arg == "-t" {
+            timeout_ms = match args.next() {
+                Some(s) => match str::parse::<u64>(&s) {
+                    Ok(ms) => ms,
+                    Err(err) => {
+                        eprintln!("Invalid timeout '{}': {}", s, err);
+                        process::exit(1);
+                    }
+                },
+                None => {
+                    eprintln!("Option 't' requires an argument");
+                    process::exit(1);
+                }
+            }
         } else if arg == "--bash" {
             state.shell = state::Shell::Bash;
         } else if arg == "--zsh" {
             state.shell = state::Shell::Zsh;
+        } else if arg == "--allow" || arg == "--deny" {
+            let res = match arg.as_str() {
+                "--allow" => project::allow(),
+                _ => project::deny(),
+            };
+
+            match res {
+                Ok(path) if arg == "--allow" => println!("Allowed {:?}", path),
+                Ok(path) => println!("Denied {:?}", path),
+                Err(err) => {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                }
+            }
+            return;
+        } else if arg == "--profile" {
+            profile_config = true;
+        } else if arg == "--check" {
+            check_config = true;
+        } else if arg == "--print-config-path" {
+            print_config_path = true;
+        } else if arg == "--print-default-config" {
+            print!("{}", DEFAULT_CONFIG);
+            return;
         } else {
             eprintln!("Unexpected argument: {}", arg);
             usage(argv0);
@@ -205,63 +403,133 @@ fn main() {
         }
     }
 
-    let config_path = match config_path {
-        Some(p) => p,
-        None => {
-            eprintln!("No config file found. Specify a config file path with '-c',");
-            eprintln!("or put a config file in <CONFIG_HOME>/starstruck.lsp.");
-            process::exit(1);
+    if print_config_path {
+        match &config_path {
+            Some(p) => println!("{} ({})", p.display(), config_reason),
+            None => println!("<default config> ({})", config_reason),
         }
-    };
+        return;
+    }
 
-    let file_string = match fs::read_to_string(&config_path) {
-        Ok(string) => string,
-        Err(err) => {
-            eprintln!("{:?}: {}", config_path, err);
-            process::exit(1);
-        }
-    };
+    if profile_config {
+        profile::enable();
+    }
+
+    // The prompt has to show up even if something hangs,
+    // so everything which might be slow gives up at the deadline
+    if timeout_ms > 0 {
+        timeout::set_deadline(Instant::now().checked_add(Duration::from_millis(timeout_ms)));
+    }
 
     let state = Rc::new(state);
-    let printer = Rc::new(RefCell::new(Printer { column: 1, row: 1 }));
+
+    let (file_string, config_name) = match &config_path {
+        Some(p) => match fs::read_to_string(p) {
+            Ok(string) => (string, p.display().to_string()),
+            Err(err) if check_config => {
+                eprintln!("{:?}: {}", p, err);
+                process::exit(1);
+            }
+            Err(err) => {
+                fallback::print(&state, &format!("{:?}: {}", p, err));
+                process::exit(1);
+            }
+        },
+        None => (DEFAULT_CONFIG.to_string(), "<default config>".to_string()),
+    };
+
+    let printer = Rc::new(RefCell::new(Printer {
+        column: 1,
+        row: 1,
+        output: String::new(),
+    }));
 
     let mut scope = eval::Scope::new();
     scope = stdlib::init(scope);
+    scope = init_segments(scope, &state);
+
+    // Project files run in the scope as it is at this point,
+    // sharing the segments' lazies without iolib, exec or import
+    let segments = scope.clone();
     scope = iolib::init(scope);
-    scope = basic::init(scope, &state);
-    scope = color::init(scope, &state);
-    scope = git::init(scope);
+    scope = timeout::init(scope);
+    scope = prefetch::init(scope);
+    scope = profile::wrap_io(scope);
+    scope = import::init(scope, config_path.as_deref());
+    scope = project::init(scope, &state, segments);
 
     {
         let s = printer.clone();
         scope = scope.put_lazy(
             "column",
-            Rc::new(move |_, scope| Ok((ValRef::Number(s.borrow().column as f64), scope))),
+            profile::wrap(
+                "column",
+                Rc::new(move |_, scope| Ok((ValRef::Number(s.borrow().column as f64), scope))),
+            ),
         );
         let s = printer.clone();
         scope = scope.put_lazy(
             "row",
-            Rc::new(move |_, scope| Ok((ValRef::Number(s.borrow().row as f64), scope))),
+            profile::wrap(
+                "row",
+                Rc::new(move |_, scope| Ok((ValRef::Number(s.borrow().row as f64), scope))),
+            ),
         );
     }
 
-    let mut reader = parse::Reader::new(
-        &file_string.as_bytes(),
-        BString::from_os_str(config_path.as_os_str()),
-    );
-    let retval = match execute_file(&mut reader, scope.clone()) {
-        Ok(val) => val,
+    if check_config {
+        match check::check(&config_name, &file_string, config_path.as_deref(), scope) {
+            0 => println!("{}: OK", config_name),
+            errors => {
+                eprintln!("{}: {} error(s)", config_name, errors);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let res = profile::time("(config)", || {
+        execute_file(&config_name, &file_string, scope)
+    });
+    let (retval, scope) = match res {
+        Ok(res) => res,
         Err(err) => {
-            eprintln!("{}", err);
+            prefetch::kill_unused();
+            profile::print();
+            fallback::print(&state, &err);
             process::exit(1);
         }
     };
 
-    match print_ps1(&printer, retval, scope) {
-        Err(err) => {
-            eprintln!("Error: {}", err);
+    let tokens = source::scan(&file_string);
+    let last_form = match source::forms(&tokens).pop() {
+        Some(form) => form,
+        None => return,
+    };
+    let elements = source::block_elements(&tokens, &last_form);
+
+    let res = profile::time("(render)", || {
+        print_config_ps1(&printer, &file_string, retval, scope, &last_form, &elements)
+    });
+    prefetch::kill_unused();
+    profile::print();
+
+    match res {
+        Err((pos, err)) => {
+            fallback::print(
+                &state,
+                &source::format_error(
+                    &config_name,
+                    &file_string,
+                    pos.line,
+                    pos.col,
+                    &format!("Error: {}", err),
+                ),
+            );
             return;
         }
         _ => (),
     };
+
+    print!("{}", printer.borrow().output);
 }
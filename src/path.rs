@@ -0,0 +1,229 @@
+use super::util;
+use osyris::bstring::BString;
+use osyris::eval::{FuncResult, Scope, StackTrace, ValRef};
+use std::rc::Rc;
+
+const ELLIPSIS: &str = "…";
+
+#[derive(Copy, Clone, PartialEq)]
+enum Style {
+    Full,
+    Fish,
+}
+
+struct ShortenOpts {
+    style: Style,
+    max_components: Option<usize>,
+    max_width: Option<usize>,
+}
+
+// Widths are counted in chars, the same way Printer counts columns
+fn width(s: &str) -> usize {
+    s.chars().count()
+}
+
+// Keep the first character of a component, or the first two if it's
+// a dotfile; '.config' turning into '.' wouldn't tell anyone anything
+fn abbreviate(component: &str) -> &str {
+    let skip = if component.starts_with('.') { 1 } else { 0 };
+    match component.char_indices().nth(skip + 1) {
+        Some((idx, _)) => &component[..idx],
+        None => component,
+    }
+}
+
+fn shorten(path: &str, opts: &ShortenOpts) -> String {
+    let mut components: Vec<&str> = path.split('/').collect();
+
+    // An absolute path starts with an empty component, which we keep
+    // out of the count and put back when joining
+    let root = if components.len() > 1 && components[0].is_empty() {
+        components.remove(0);
+        true
+    } else {
+        false
+    };
+
+    let mut elided = false;
+    if let Some(max) = opts.max_components {
+        let max = max.max(1);
+        if components.len() > max {
+            components.drain(..components.len() - max);
+            elided = true;
+        }
+    }
+
+    let last = components.len().saturating_sub(1);
+    let mut parts: Vec<&str> = Vec::new();
+    if elided {
+        parts.push(ELLIPSIS);
+    } else if root {
+        parts.push("");
+    }
+
+    for (idx, component) in components.iter().enumerate() {
+        if opts.style == Style::Fish && idx != last {
+            parts.push(abbreviate(component));
+        } else {
+            parts.push(component);
+        }
+    }
+
+    let mut shortened = parts.join("/");
+    if let Some(max) = opts.max_width {
+        let w = width(&shortened);
+        if max == 0 {
+            shortened.clear();
+        } else if w > max {
+            let keep = max.saturating_sub(width(ELLIPSIS));
+            let tail: String = shortened.chars().skip(w - keep).collect();
+            shortened = format!("{}{}", ELLIPSIS, tail);
+        }
+    }
+
+    shortened
+}
+
+fn number_arg(key: &str, val: &ValRef) -> Result<usize, StackTrace> {
+    match val {
+        ValRef::Number(num) if *num >= 0.0 => Ok(*num as usize),
+        _ => Err(StackTrace::from_string(format!(
+            "'shorten-path' option '{}' requires a non-negative number",
+            key
+        ))),
+    }
+}
+
+// (shorten-path path ['style 'fish] ['max-components n] ['max-width n])
+fn shorten_path(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    if args.is_empty() || args.len() % 2 != 1 {
+        return Err(StackTrace::from_str(
+            "'shorten-path' requires a path followed by key/value pairs",
+        ));
+    }
+
+    let path = match &args[0] {
+        ValRef::String(s) => String::from_utf8_lossy(s.as_bytes()).to_string(),
+        _ => {
+            return Err(StackTrace::from_str(
+                "'shorten-path' requires a string as its first argument",
+            ))
+        }
+    };
+
+    let mut opts = ShortenOpts {
+        style: Style::Full,
+        max_components: None,
+        max_width: None,
+    };
+
+    for pair in args[1..].chunks(2) {
+        let key = match util::as_name(&pair[0]) {
+            Some(s) => String::from_utf8_lossy(s.as_bytes()).to_string(),
+            None => return Err(StackTrace::from_str("'shorten-path' keys must be strings")),
+        };
+
+        // Accept ':style' as well as 'style'
+        let key = key.strip_prefix(':').unwrap_or(&key);
+        match key {
+            "style" => {
+                opts.style = match util::as_name(&pair[1]).map(|s| s.as_bytes()) {
+                    Some(b"fish") => Style::Fish,
+                    Some(b"full") => Style::Full,
+                    _ => {
+                        return Err(StackTrace::from_str(
+                            "'shorten-path' style must be 'fish' or 'full'",
+                        ))
+                    }
+                }
+            }
+            "max-components" => opts.max_components = Some(number_arg(key, &pair[1])?),
+            "max-width" => opts.max_width = Some(number_arg(key, &pair[1])?),
+            _ => {
+                return Err(StackTrace::from_string(format!(
+                    "'shorten-path' got unknown option '{}'",
+                    key
+                )))
+            }
+        }
+    }
+
+    Ok((
+        ValRef::String(Rc::new(BString::from_string(shorten(&path, &opts)))),
+        scope,
+    ))
+}
+
+pub fn init(mut s: Scope) -> Scope {
+    s = s.put_func("shorten-path", Rc::new(shorten_path));
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opts(style: Style, max_components: Option<usize>, max_width: Option<usize>) -> ShortenOpts {
+        ShortenOpts {
+            style,
+            max_components,
+            max_width,
+        }
+    }
+
+    #[test]
+    fn styles() {
+        for (path, full, fish) in [
+            ("/usr/local/bin", "/usr/local/bin", "/u/l/bin"),
+            (
+                "~/.config/starstruck/src",
+                "~/.config/starstruck/src",
+                "~/.c/s/src",
+            ),
+            ("src/sys", "src/sys", "s/sys"),
+            ("/", "/", "/"),
+            ("~", "~", "~"),
+        ] {
+            assert_eq!(shorten(path, &opts(Style::Full, None, None)), full);
+            assert_eq!(shorten(path, &opts(Style::Fish, None, None)), fish);
+        }
+    }
+
+    #[test]
+    fn max_components() {
+        let path = "/usr/local/share/doc";
+        for (max, full, fish) in [
+            (5, "/usr/local/share/doc", "/u/l/s/doc"),
+            (4, "/usr/local/share/doc", "/u/l/s/doc"),
+            (2, "…/share/doc", "…/s/doc"),
+            (1, "…/doc", "…/doc"),
+            // There's always at least one component
+            (0, "…/doc", "…/doc"),
+        ] {
+            assert_eq!(shorten(path, &opts(Style::Full, Some(max), None)), full);
+            assert_eq!(shorten(path, &opts(Style::Fish, Some(max), None)), fish);
+        }
+    }
+
+    #[test]
+    fn max_width() {
+        let path = "/usr/local/share/doc";
+        for (max, expected) in [
+            (100, "/usr/local/share/doc"),
+            (20, "/usr/local/share/doc"),
+            (19, "…sr/local/share/doc"),
+            (10, "…share/doc"),
+            (1, "…"),
+            (0, ""),
+        ] {
+            assert_eq!(shorten(path, &opts(Style::Full, None, Some(max))), expected);
+        }
+
+        // Widths are counted in chars, not bytes
+        assert_eq!(
+            shorten("/é/ü/ö", &opts(Style::Full, None, Some(5))),
+            "…/ü/ö"
+        );
+        assert_eq!(shorten(path, &opts(Style::Fish, Some(2), Some(4))), "…doc");
+    }
+}
@@ -0,0 +1,105 @@
+use super::timeout::{self, Running};
+use super::util;
+use osyris::eval::{FuncResult, Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::rc::Rc;
+
+type Prefetcher = Rc<dyn Fn()>;
+
+// Everything in the prompt is evaluated in order, so slow parts add up.
+// Prefetching starts the slow parts early, on other threads or as child
+// processes, so that they run at the same time; later uses of the lazy
+// or the command wait for the result rather than starting over.
+//
+// Modules register a function for each lazy which can be prefetched,
+// which starts gathering the lazy's data on another thread.
+thread_local! {
+    static PREFETCHERS: RefCell<HashMap<String, Vec<Prefetcher>>> =
+        RefCell::new(HashMap::new());
+    static EXECS: RefCell<HashMap<Vec<OsString>, Vec<Running>>> =
+        RefCell::new(HashMap::new());
+}
+
+pub fn register(name: &str, f: Prefetcher) {
+    PREFETCHERS.with(|p| p.borrow_mut().entry(name.to_string()).or_default().push(f));
+}
+
+// Takes a program which was started by 'prefetch-exec' with the same arguments
+pub fn take_exec(argv: &[OsString]) -> Option<Running> {
+    EXECS.with(|e| e.borrow_mut().get_mut(argv)?.pop())
+}
+
+// Kills the programs started by 'prefetch-exec' which no 'exec' used,
+// so that they don't outlive the prompt
+pub fn kill_unused() {
+    let execs = EXECS.with(|e| std::mem::take(&mut *e.borrow_mut()));
+    for running in execs.into_values().flatten() {
+        timeout::kill(running);
+    }
+}
+
+// (prefetch name...)
+// Starts gathering the data for the named lazies, like 'git-branch',
+// or for every lazy in a group, like 'git' or 'toolchain'
+fn prefetch(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    for arg in &args {
+        let name = match util::as_name(arg) {
+            Some(s) => s.to_string(),
+            None => return Err(StackTrace::from_str("'prefetch' requires string arguments")),
+        };
+
+        let prefetchers = PREFETCHERS.with(|p| p.borrow().get(&name).cloned());
+        match prefetchers {
+            Some(prefetchers) => prefetchers.iter().for_each(|f| f()),
+            None => {
+                return Err(StackTrace::from_string(format!(
+                    "'prefetch': '{}' can't be prefetched",
+                    name
+                )))
+            }
+        }
+    }
+
+    Ok((ValRef::None, scope))
+}
+
+// (prefetch-exec program args...)
+// Starts a program, whose output is returned by a later 'exec'
+// with the same arguments
+fn prefetch_exec(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let argv = timeout::argv("prefetch-exec", &args)?;
+    if let Some(running) = timeout::start(&argv)? {
+        EXECS.with(|e| e.borrow_mut().entry(argv).or_default().push(running));
+    }
+    Ok((ValRef::None, scope))
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    scope = scope.put_func("prefetch", Rc::new(prefetch));
+    scope = scope.put_func("prefetch-exec", Rc::new(prefetch_exec));
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use osyris::bstring::BString;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn kills_unused_programs() {
+        let args: Vec<ValRef> = ["sleep", "30"]
+            .iter()
+            .map(|s| ValRef::String(Rc::new(BString::from_str(s))))
+            .collect();
+        let argv = timeout::argv("prefetch-exec", &args).unwrap();
+        assert!(prefetch_exec(args, Scope::new()).is_ok());
+
+        let start = Instant::now();
+        kill_unused();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(take_exec(&argv).is_none());
+    }
+}
@@ -0,0 +1,110 @@
+use osyris::eval::{self, FuncResult, Scope, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+type Func = Rc<dyn Fn(Vec<ValRef>, Scope) -> FuncResult>;
+
+#[derive(Default)]
+struct Entry {
+    total: Duration,
+    calls: u32,
+}
+
+#[derive(Default)]
+struct Profile {
+    entries: HashMap<String, Entry>,
+}
+
+// The profile only exists with --profile. Everything is wrapped when the
+// scope is set up, so without --profile, nothing is wrapped at all.
+// Times are inclusive; a lazy which uses another lazy includes its time.
+thread_local! {
+    static PROFILE: RefCell<Option<Profile>> = const { RefCell::new(None) };
+}
+
+pub fn enable() {
+    PROFILE.with(|p| *p.borrow_mut() = Some(Profile::default()));
+}
+
+fn is_enabled() -> bool {
+    PROFILE.with(|p| p.borrow().is_some())
+}
+
+fn record(name: &str, dur: Duration) {
+    PROFILE.with(|p| {
+        if let Some(profile) = p.borrow_mut().as_mut() {
+            let entry = profile.entries.entry(name.to_string()).or_default();
+            entry.total += dur;
+            entry.calls += 1;
+        }
+    });
+}
+
+pub fn time<T>(name: &str, f: impl FnOnce() -> T) -> T {
+    if !is_enabled() {
+        return f();
+    }
+
+    let start = Instant::now();
+    let res = f();
+    record(name, start.elapsed());
+    res
+}
+
+pub fn wrap(name: &str, f: Func) -> Func {
+    if !is_enabled() {
+        return f;
+    }
+
+    let name = name.to_string();
+    Rc::new(move |args, scope| time(&name, || f(args, scope)))
+}
+
+// Wraps 'exec', so that commands show up as 'exec <argv>'
+pub fn wrap_io(mut scope: Scope) -> Scope {
+    if !is_enabled() {
+        return scope;
+    }
+
+    if let Ok(exec) = super::lookup("exec", &scope) {
+        scope = scope.put_func(
+            "exec",
+            Rc::new(move |args, scope| {
+                let argv: Vec<String> = args
+                    .iter()
+                    .map(|arg| match arg {
+                        ValRef::String(s) => s.to_string(),
+                        _ => "?".to_string(),
+                    })
+                    .collect();
+                let name = format!("exec {}", argv.join(" "));
+                time(&name, || eval::call(&exec, args, scope))
+            }),
+        );
+    }
+
+    scope
+}
+
+// Prints a table of where time was spent, slowest first, to stderr
+pub fn print() {
+    let profile = match PROFILE.with(|p| p.borrow_mut().take()) {
+        Some(profile) => profile,
+        None => return,
+    };
+
+    let mut entries: Vec<(String, Entry)> = profile.entries.into_iter().collect();
+    entries.sort_by(|a, b| b.1.total.cmp(&a.1.total).then_with(|| a.0.cmp(&b.0)));
+
+    eprintln!("{:>10} {:>6}  name", "ms", "calls");
+    for (name, entry) in entries {
+        eprintln!(
+            "{:>10.3} {:>6}  {}",
+            entry.total.as_secs_f64() * 1000.0,
+            entry.calls,
+            name
+        );
+    }
+}
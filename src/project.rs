@@ -0,0 +1,330 @@
+use super::color;
+use super::cwd;
+use super::profile;
+use super::state::State;
+use super::timeout;
+use super::util;
+use super::UncountedString;
+use osyris::bstring::BString;
+use osyris::eval::{self, Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+const PROJECT_FILE: &str = ".starstruck.lsp";
+
+// A project file is only run once the user has allowed it with --allow,
+// and only for as long as its content stays the same; like direnv's
+// allow mechanism. The trust dir contains a copy of each allowed file,
+// named after its path, so that a changed file is never trusted.
+//
+// Trusted files run in a scope without iolib or import, so they can
+// compute segments from the same information as the main config,
+// but can't run programs or read arbitrary files.
+struct ProjectCtx {
+    state: Rc<State>,
+    scope: Scope,
+    file: Option<Option<Rc<ProjectFile>>>,
+    segment: Option<ValRef>,
+}
+
+struct ProjectFile {
+    path: PathBuf,
+    content: Vec<u8>,
+    trusted: bool,
+}
+
+fn find_project_file(starts: &[PathBuf]) -> Option<PathBuf> {
+    starts
+        .iter()
+        .flat_map(|cwd| cwd.ancestors())
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+fn trust_dir() -> Option<PathBuf> {
+    let mut dir = dirs::data_dir()?;
+    dir.push("starstruck");
+    dir.push("trusted");
+    Some(dir)
+}
+
+// Trust files contain the project file's path on the first line,
+// followed by the content which was allowed
+fn trust_entry(path: &Path) -> Option<(PathBuf, Vec<u8>)> {
+    let path_str = path.to_str()?;
+    let mut entry = trust_dir()?;
+    // The hash is only used to get a file name from the path,
+    // the trust check compares the whole content
+    entry.push(format!("{:016x}", util::fnv1a_64(path_str.as_bytes())));
+
+    let mut content = Vec::new();
+    content.extend_from_slice(path_str.as_bytes());
+    content.push(b'\n');
+    Some((entry, content))
+}
+
+fn is_trusted(path: &Path, content: &[u8]) -> bool {
+    let (entry, mut expected) = match trust_entry(path) {
+        Some(entry) => entry,
+        None => return false,
+    };
+
+    expected.extend_from_slice(content);
+    match fs::read(entry) {
+        Ok(trusted) => trusted == expected,
+        Err(..) => false,
+    }
+}
+
+fn find_canonical_project_file() -> Result<PathBuf, String> {
+    let path = match find_project_file(&cwd::search_starts()) {
+        Some(path) => path,
+        None => return Err(format!("No {} found", PROJECT_FILE)),
+    };
+
+    fs::canonicalize(&path).map_err(|err| format!("{:?}: {}", path, err))
+}
+
+fn trust(path: &Path, content: &[u8]) -> Result<(), String> {
+    let (entry, mut trusted) = match trust_entry(path) {
+        Some(entry) => entry,
+        None => return Err(format!("{:?}: Can't store trust for this path", path)),
+    };
+
+    trusted.extend_from_slice(content);
+    if let Some(dir) = entry.parent() {
+        fs::create_dir_all(dir).map_err(|err| format!("{:?}: {}", dir, err))?;
+    }
+    fs::write(&entry, trusted).map_err(|err| format!("{:?}: {}", entry, err))
+}
+
+// Trusts the project file for the cwd, with its current content
+pub fn allow() -> Result<PathBuf, String> {
+    let path = find_canonical_project_file()?;
+    let content = fs::read(&path).map_err(|err| format!("{:?}: {}", path, err))?;
+    trust(&path, &content)?;
+    Ok(path)
+}
+
+pub fn deny() -> Result<PathBuf, String> {
+    let path = find_canonical_project_file()?;
+    if let Some((entry, _)) = trust_entry(&path) {
+        if entry.exists() {
+            fs::remove_file(&entry).map_err(|err| format!("{:?}: {}", entry, err))?;
+        }
+    }
+    Ok(path)
+}
+
+// Walking up the tree, reading the file and checking it against the trust
+// dir are all done in one go on another thread
+fn find_file(ctx: &Rc<RefCell<ProjectCtx>>) -> Option<Rc<ProjectFile>> {
+    if let Some(file) = &ctx.borrow().file {
+        return file.clone();
+    }
+
+    let starts = cwd::search_starts();
+    let file = timeout::run(move || {
+        let path = fs::canonicalize(find_project_file(&starts)?).ok()?;
+        let (content, trusted) = match fs::read(&path) {
+            Ok(content) => {
+                let trusted = is_trusted(&path, &content);
+                (content, trusted)
+            }
+            Err(..) => (Vec::new(), false),
+        };
+
+        Some(ProjectFile {
+            path,
+            content,
+            trusted,
+        })
+    });
+
+    let file = file.flatten().map(Rc::new);
+    ctx.borrow_mut().file = Some(file.clone());
+    file
+}
+
+// Evaluates everything the project file returned in its own scope,
+// so that blocks and functions from the project file never end up
+// being called in the main config's scope
+fn flatten(val: ValRef, mut scope: Scope, out: &mut Vec<ValRef>) -> Result<Scope, StackTrace> {
+    match val {
+        ValRef::None => (),
+        ValRef::List(lst) => {
+            for item in lst.borrow().iter() {
+                scope = flatten(item.clone(), scope, out)?;
+            }
+        }
+        ValRef::Block(exprs) => {
+            for expr in exprs.iter() {
+                let (val, s) = eval::eval(expr, scope)?;
+                scope = flatten(val, s, out)?;
+            }
+        }
+        ValRef::Func(..) | ValRef::Lazy(..) | ValRef::ProtectedLazy(..) => {
+            let (val, s) = eval::call(&val, vec![], scope)?;
+            scope = flatten(val, s, out)?;
+        }
+        ValRef::Dict(..) | ValRef::Port(..) => (),
+        val => out.push(val),
+    }
+
+    Ok(scope)
+}
+
+fn load(scope: Scope, path: &Path, content: &[u8]) -> Result<ValRef, StackTrace> {
+    let content = String::from_utf8_lossy(content);
+    let (val, scope) = super::execute_file(&path.display().to_string(), &content, scope)
+        .map_err(StackTrace::from_string)?;
+
+    let mut out = Vec::new();
+    flatten(val, scope, &mut out)
+        .map_err(|err| StackTrace::from_string(format!("{}: Error: {}", path.display(), err)))?;
+    Ok(ValRef::List(Rc::new(RefCell::new(out))))
+}
+
+// A broken project file mustn't take the whole prompt down with it,
+// so its error goes to stderr, and the segment becomes a short marker
+fn error_marker(state: &State, err: &StackTrace) -> ValRef {
+    eprintln!("{}", err);
+
+    let shell = state.shell;
+    let escaped = |col: &str| {
+        ValRef::Native(Rc::new(UncountedString {
+            s: format!("{}{}{}", shell.escape_start(), col, shell.escape_end()),
+        }))
+    };
+
+    let marker = format!("[{} error]", PROJECT_FILE);
+    ValRef::List(Rc::new(RefCell::new(vec![
+        escaped(color::RED),
+        ValRef::String(Rc::new(BString::from_string(marker))),
+        escaped(color::RESET),
+    ])))
+}
+
+// The project file is run the first time its segment is used,
+// which is after the main config has been run
+fn project_segment(ctx: &Rc<RefCell<ProjectCtx>>) -> Result<ValRef, StackTrace> {
+    if let Some(segment) = &ctx.borrow().segment {
+        return Ok(segment.clone());
+    }
+
+    let segment = match find_file(ctx) {
+        Some(file) if file.trusted => {
+            let (state, scope) = {
+                let c = ctx.borrow();
+                (c.state.clone(), c.scope.clone())
+            };
+            match load(scope, &file.path, &file.content) {
+                Ok(segment) => segment,
+                Err(err) => error_marker(&state, &err),
+            }
+        }
+        _ => ValRef::None,
+    };
+
+    ctx.borrow_mut().segment = Some(segment.clone());
+    Ok(segment)
+}
+
+fn project_untrusted(ctx: &Rc<RefCell<ProjectCtx>>) -> Result<ValRef, StackTrace> {
+    match find_file(ctx) {
+        Some(file) => Ok(ValRef::Bool(!file.trusted)),
+        None => Ok(ValRef::Bool(false)),
+    }
+}
+
+// 'segments' is the scope project files run in, with the same segment
+// lazies as the main config, but without iolib or import
+pub fn init(mut scope: Scope, state: &Rc<State>, segments: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(ProjectCtx {
+        state: state.clone(),
+        scope: segments,
+        file: None,
+        segment: None,
+    }));
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
+        };
+    }
+
+    put!("project-segment", project_segment);
+    put!("project-untrusted?", project_untrusted);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::{fixture, ENV_LOCK};
+    use std::env;
+
+    // Points the trust dir into a fresh fixture directory
+    fn trust_fixture() -> PathBuf {
+        let dir = fixture(&[]);
+        env::set_var("XDG_DATA_HOME", &dir);
+        dir
+    }
+
+    #[test]
+    fn untrusted() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let _dir = trust_fixture();
+        assert!(!is_trusted(
+            Path::new("/src/foo/.starstruck.lsp"),
+            b"\"hi\""
+        ));
+    }
+
+    #[test]
+    fn trusted_unchanged() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let dir = trust_fixture();
+        let path = Path::new("/src/foo/.starstruck.lsp");
+        trust(path, b"\"hi\"").unwrap();
+        assert!(is_trusted(path, b"\"hi\""));
+
+        // The entry is named after the path, and holds the path and the content
+        let entry = dir.join("starstruck/trusted").join(format!(
+            "{:016x}",
+            util::fnv1a_64(b"/src/foo/.starstruck.lsp")
+        ));
+        assert_eq!(
+            fs::read(entry).unwrap(),
+            b"/src/foo/.starstruck.lsp\n\"hi\""
+        );
+    }
+
+    #[test]
+    fn trusted_then_edited() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let _dir = trust_fixture();
+        let path = Path::new("/src/foo/.starstruck.lsp");
+        trust(path, b"\"hi\"").unwrap();
+        assert!(!is_trusted(path, b"\"hi\" (exec \"rm\")"));
+        assert!(!is_trusted(path, b"\"hi"));
+        assert!(!is_trusted(path, b""));
+    }
+
+    #[test]
+    fn trust_is_per_path() {
+        let _lock = ENV_LOCK.lock().unwrap();
+        let _dir = trust_fixture();
+        trust(Path::new("/src/foo/.starstruck.lsp"), b"\"hi\"").unwrap();
+        assert!(!is_trusted(
+            Path::new("/src/bar/.starstruck.lsp"),
+            b"\"hi\""
+        ));
+    }
+}
@@ -0,0 +1,103 @@
+use super::profile;
+use super::timeout;
+use super::util::non_empty_var_os;
+use osyris::bstring::BString;
+use osyris::eval::{FuncResult, Scope, ValRef};
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::PathBuf;
+use std::rc::Rc;
+
+fn virtualenv(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let venv = match non_empty_var_os("VIRTUAL_ENV") {
+        Some(venv) => venv,
+        None => return Ok((ValRef::None, scope)),
+    };
+
+    // Newer virtualenvs set VIRTUAL_ENV_PROMPT to the name the user chose
+    // with '--prompt'. Older ones set it to the whole "(name) " prefix.
+    if let Ok(prompt) = env::var("VIRTUAL_ENV_PROMPT") {
+        let prompt = prompt.trim();
+        let prompt = match prompt.strip_prefix('(') {
+            Some(p) => p.strip_suffix(')').unwrap_or(p),
+            None => prompt,
+        };
+
+        if !prompt.is_empty() {
+            return Ok((ValRef::String(Rc::new(BString::from_str(prompt))), scope));
+        }
+    }
+
+    let path = PathBuf::from(venv);
+    match path.file_name() {
+        Some(name) => Ok((ValRef::String(Rc::new(BString::from_os_str(name))), scope)),
+        None => Ok((ValRef::None, scope)),
+    }
+}
+
+fn conda_env(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    match non_empty_var_os("CONDA_DEFAULT_ENV") {
+        Some(name) => Ok((ValRef::String(Rc::new(BString::from_os_str(&name))), scope)),
+        None => Ok((ValRef::None, scope)),
+    }
+}
+
+fn read_pyvenv_version(mut path: PathBuf) -> Option<String> {
+    path.push("pyvenv.cfg");
+    let f = match fs::File::open(&path) {
+        Err(..) => return None,
+        Ok(f) => f,
+    };
+
+    // The file looks like:
+    //   home = /usr/bin
+    //   version = 3.10.4
+    // 'venv' calls the key 'version', 'virtualenv' calls it 'version_info'
+    for line in BufReader::new(f).lines() {
+        let line = match line {
+            Err(..) => return None,
+            Ok(line) => line,
+        };
+
+        let (key, val) = match line.split_once('=') {
+            Some(kv) => kv,
+            None => continue,
+        };
+
+        let key = key.trim();
+        if key == "version" || key == "version_info" {
+            return Some(val.trim().to_string());
+        }
+    }
+
+    None
+}
+
+fn python_version(_: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let venv = match non_empty_var_os("VIRTUAL_ENV") {
+        Some(venv) => venv,
+        None => return Ok((ValRef::None, scope)),
+    };
+
+    match timeout::run(move || read_pyvenv_version(PathBuf::from(venv))).flatten() {
+        Some(version) => Ok((
+            ValRef::String(Rc::new(BString::from_string(version))),
+            scope,
+        )),
+        None => Ok((ValRef::None, scope)),
+    }
+}
+
+pub fn init(mut s: Scope) -> Scope {
+    s = s.put_lazy(
+        "virtualenv",
+        profile::wrap("virtualenv", Rc::new(virtualenv)),
+    );
+    s = s.put_lazy("conda-env", profile::wrap("conda-env", Rc::new(conda_env)));
+    s = s.put_lazy(
+        "python-version",
+        profile::wrap("python-version", Rc::new(python_version)),
+    );
+    s
+}
@@ -0,0 +1,213 @@
+use super::profile;
+use super::sys::{self, Process};
+use osyris::bstring::BString;
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::env;
+use std::rc::Rc;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum RemoteKind {
+    None,
+    Ssh,
+    Mosh,
+}
+
+impl RemoteKind {
+    fn name(self) -> &'static str {
+        match self {
+            RemoteKind::None => "none",
+            RemoteKind::Ssh => "ssh",
+            RemoteKind::Mosh => "mosh",
+        }
+    }
+}
+
+struct RemoteCtx {
+    has_detected: bool,
+    kind: RemoteKind,
+    client_ip: Option<String>,
+}
+
+// SSH_CONNECTION is 'client-ip client-port server-ip server-port',
+// SSH_CLIENT is 'client-ip client-port server-port'
+fn client_ip_from(val: &str) -> Option<String> {
+    val.split_whitespace().next().map(|ip| ip.to_string())
+}
+
+fn client_ip_from_env() -> Option<String> {
+    for key in ["SSH_CONNECTION", "SSH_CLIENT"] {
+        if let Ok(val) = env::var(key) {
+            if let Some(ip) = client_ip_from(&val) {
+                return Some(ip);
+            }
+        }
+    }
+
+    None
+}
+
+// The SSH_* variables are lost after 'sudo -i' or 'su -', and can be
+// left over from somewhere else, so when the process tree is available,
+// it decides: the prompt is remote if sshd or mosh-server is an ancestor.
+// Under tmux or screen, the tree ends at the server, which doesn't say
+// where the client is; only the environment is left to go by there,
+// even though it's stale when a session started over SSH is reattached locally.
+fn detect_kind(ancestors: &[Process], has_ssh_env: bool) -> RemoteKind {
+    // mosh-server is started over SSH, so its children inherit
+    // SSH_CONNECTION; check for it before trusting the environment
+    let mosh = ancestors.iter().any(|p| p.name == "mosh-server");
+    let sshd = ancestors.iter().any(|p| p.name.starts_with("sshd"));
+    let in_multiplexer = ancestors
+        .iter()
+        .any(|p| p.name.starts_with("tmux") || p.name.eq_ignore_ascii_case("screen"));
+    let trust_tree = !ancestors.is_empty() && !in_multiplexer;
+
+    if mosh {
+        RemoteKind::Mosh
+    } else if sshd || (has_ssh_env && !trust_tree) {
+        RemoteKind::Ssh
+    } else {
+        RemoteKind::None
+    }
+}
+
+// If our own environment was scrubbed, an ancestor (like the sudo or su
+// process, or the shell it was started from) might still have it
+fn client_ip_from_ancestors(
+    ancestors: &[Process],
+    env_var: impl Fn(u32, &str) -> Option<String>,
+) -> Option<String> {
+    ancestors.iter().find_map(|p| {
+        let val = env_var(p.pid, "SSH_CONNECTION").or_else(|| env_var(p.pid, "SSH_CLIENT"))?;
+        client_ip_from(&val)
+    })
+}
+
+impl RemoteCtx {
+    fn new() -> Self {
+        Self {
+            has_detected: false,
+            kind: RemoteKind::None,
+            client_ip: None,
+        }
+    }
+
+    fn detect(&mut self) {
+        if self.has_detected {
+            return;
+        }
+        self.has_detected = true;
+
+        let ancestors = sys::process_ancestors();
+        let has_ssh_env = ["SSH_CONNECTION", "SSH_CLIENT", "SSH_TTY"]
+            .iter()
+            .any(|key| env::var_os(key).is_some());
+
+        self.kind = detect_kind(&ancestors, has_ssh_env);
+        if self.kind == RemoteKind::None {
+            return;
+        }
+
+        self.client_ip = client_ip_from_env()
+            .or_else(|| client_ip_from_ancestors(&ancestors, sys::process_env_var));
+    }
+}
+
+fn is_remote(ctx: &Rc<RefCell<RemoteCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().detect();
+    Ok(ValRef::Bool(ctx.borrow().kind != RemoteKind::None))
+}
+
+fn remote_kind(ctx: &Rc<RefCell<RemoteCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().detect();
+    Ok(ValRef::String(Rc::new(BString::from_str(
+        ctx.borrow().kind.name(),
+    ))))
+}
+
+fn remote_client_ip(ctx: &Rc<RefCell<RemoteCtx>>) -> Result<ValRef, StackTrace> {
+    ctx.borrow_mut().detect();
+    match &ctx.borrow().client_ip {
+        Some(ip) => Ok(ValRef::String(Rc::new(BString::from_str(ip)))),
+        None => Ok(ValRef::None),
+    }
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(RemoteCtx::new()));
+
+    macro_rules! put {
+        ($name: expr, $func: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap($name, Rc::new(move |_, scope| Ok(($func(&c)?, scope)))),
+            );
+        };
+    }
+
+    put!("is-remote?", is_remote);
+    put!("remote-kind", remote_kind);
+    put!("remote-client-ip", remote_client_ip);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn procs(names: &[&str]) -> Vec<Process> {
+        names
+            .iter()
+            .enumerate()
+            .map(|(idx, name)| Process {
+                pid: 100 + idx as u32,
+                name: name.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kinds() {
+        use RemoteKind::*;
+        for (tree, has_ssh_env, expected) in [
+            (&["bash", "sshd: user@pts/0", "sshd"][..], true, Ssh),
+            // The tree wins over a scrubbed or left over environment
+            (&["bash", "sudo", "bash", "sshd"], false, Ssh),
+            (&["bash", "gnome-terminal-server", "systemd"], true, None),
+            (&["bash", "mosh-server"], true, Mosh),
+            (&["bash", "mosh-server"], false, Mosh),
+            // Under a multiplexer, or without a tree, only the environment is left
+            (&["bash", "tmux: server"], true, Ssh),
+            (&["bash", "tmux: server"], false, None),
+            (&["bash", "SCREEN"], true, Ssh),
+            (&[], true, Ssh),
+            (&[], false, None),
+        ] {
+            assert_eq!(detect_kind(&procs(tree), has_ssh_env), expected);
+        }
+    }
+
+    #[test]
+    fn ancestor_client_ip() {
+        let ancestors = procs(&["sudo", "bash", "sshd"]);
+        let env_var = |pid: u32, key: &str| match (pid, key) {
+            (101, "SSH_CLIENT") => Some("10.0.0.1 5000 22".to_string()),
+            (102, "SSH_CONNECTION") => Some("10.0.0.2 5000 10.0.0.3 22".to_string()),
+            _ => Option::None,
+        };
+        assert_eq!(
+            client_ip_from_ancestors(&ancestors, env_var).as_deref(),
+            Some("10.0.0.1")
+        );
+        assert_eq!(
+            client_ip_from_ancestors(&ancestors[2..], env_var).as_deref(),
+            Some("10.0.0.2")
+        );
+        assert_eq!(
+            client_ip_from_ancestors(&ancestors[..1], env_var),
+            Option::None
+        );
+    }
+}
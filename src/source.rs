@@ -0,0 +1,352 @@
+// A scanner for osyris source, which only knows enough of the syntax
+// to find identifiers and the extent of forms, with their positions.
+// The osyris parser doesn't keep positions around after parsing,
+// so this is used to point at code in --check and in error messages.
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct Pos {
+    pub offset: usize,
+    pub line: usize,
+    pub col: usize,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum TokenKind {
+    Open,
+    Close,
+    String,
+    Number,
+    Ident,
+
+    // A quoted name like 'foo, which evaluates to the string "foo"
+    Quote,
+}
+
+#[derive(Clone, Debug)]
+pub struct Token<'a> {
+    pub kind: TokenKind,
+    pub pos: Pos,
+    pub text: &'a str,
+}
+
+// A top-level expression, or an expression directly inside a block
+#[derive(Clone, Debug)]
+pub struct Form {
+    pub start: Pos,
+    pub end: usize,
+}
+
+fn is_delimiter(ch: char) -> bool {
+    ch.is_whitespace() || "(){}[];\"'".contains(ch)
+}
+
+pub fn scan(src: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    let mut line = 1;
+    let mut col = 1;
+
+    macro_rules! advance {
+        () => {{
+            let c = chars.next();
+            if let Some((_, ch)) = c {
+                if ch == '\n' {
+                    line += 1;
+                    col = 1;
+                } else {
+                    col += 1;
+                }
+            }
+            c
+        }};
+    }
+
+    while let Some(&(offset, ch)) = chars.peek() {
+        let pos = Pos { offset, line, col };
+        if ch.is_whitespace() {
+            advance!();
+            continue;
+        } else if ch == ';' {
+            while let Some(&(_, ch)) = chars.peek() {
+                if ch == '\n' {
+                    break;
+                }
+                advance!();
+            }
+            continue;
+        }
+
+        let kind = if "({[".contains(ch) {
+            advance!();
+            TokenKind::Open
+        } else if ")}]".contains(ch) {
+            advance!();
+            TokenKind::Close
+        } else if ch == '"' {
+            advance!();
+            while let Some((_, ch)) = advance!() {
+                if ch == '\\' {
+                    advance!();
+                } else if ch == '"' {
+                    break;
+                }
+            }
+            TokenKind::String
+        } else {
+            let kind = if ch == '\'' {
+                advance!();
+                TokenKind::Quote
+            } else {
+                TokenKind::Ident
+            };
+
+            while let Some(&(_, ch)) = chars.peek() {
+                if is_delimiter(ch) {
+                    break;
+                }
+                advance!();
+            }
+            kind
+        };
+
+        let end = match chars.peek() {
+            Some(&(end, _)) => end,
+            None => src.len(),
+        };
+        let text = &src[offset..end];
+        let kind = match kind {
+            TokenKind::Ident if str::parse::<f64>(text).is_ok() => TokenKind::Number,
+            kind => kind,
+        };
+
+        tokens.push(Token { kind, pos, text });
+    }
+
+    tokens
+}
+
+// Splits a sequence of tokens into forms at depth 0.
+// An unterminated form extends to the end of the tokens.
+pub fn forms(tokens: &[Token]) -> Vec<Form> {
+    let mut forms = Vec::new();
+    let mut depth = 0;
+    let mut start = None;
+
+    for tok in tokens {
+        if start.is_none() {
+            start = Some(tok.pos);
+        }
+
+        match tok.kind {
+            TokenKind::Open => depth += 1,
+            TokenKind::Close if depth > 0 => depth -= 1,
+            _ => (),
+        }
+
+        if depth == 0 {
+            forms.push(Form {
+                start: start.take().unwrap(),
+                end: tok.pos.offset + tok.text.len(),
+            });
+        }
+    }
+
+    if let (Some(start), Some(last)) = (start, tokens.last()) {
+        forms.push(Form {
+            start,
+            end: last.pos.offset + last.text.len(),
+        });
+    }
+
+    forms
+}
+
+// The elements of a block form, like the segments of a config
+// which returns '{ ... }'. Empty for forms which aren't blocks.
+pub fn block_elements(tokens: &[Token], form: &Form) -> Vec<Form> {
+    let start = match tokens.iter().position(|tok| tok.pos == form.start) {
+        Some(start) if tokens[start].text == "{" => start + 1,
+        _ => return Vec::new(),
+    };
+
+    let end = tokens[start..]
+        .iter()
+        .position(|tok| tok.pos.offset + tok.text.len() == form.end)
+        .map(|end| start + end)
+        .unwrap_or(tokens.len());
+    if end < start || tokens.get(end).map(|tok| tok.text) != Some("}") {
+        return Vec::new();
+    }
+
+    forms(&tokens[start..end])
+}
+
+// The source of a form on its own, padded with newlines and spaces
+// so that the parser's positions match positions in the whole file
+pub fn form_text(src: &str, form: &Form) -> String {
+    let mut text = "\n".repeat(form.start.line - 1);
+    text += &" ".repeat(form.start.col - 1);
+    text += &src[form.start.offset..form.end];
+    text
+}
+
+// Formats an error as 'file:line:col: msg', followed by the line
+// it refers to, with a caret under the column
+pub fn format_error(name: &str, src: &str, line: usize, col: usize, msg: &str) -> String {
+    let mut s = format!("{}:{}:{}: {}", name, line, col, msg);
+    let text = match line.checked_sub(1).and_then(|l| src.lines().nth(l)) {
+        Some(text) => text,
+        None => return s,
+    };
+
+    // Tabs are kept, so that the caret lines up with the text above it
+    let indent: String = text
+        .chars()
+        .take(col.saturating_sub(1))
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect();
+    let num = line.to_string();
+    let pad = " ".repeat(num.len());
+    s += &format!("\n{} |\n{} | {}\n{} | {}^", pad, num, text, pad, indent);
+    s
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn kinds_and_texts(src: &str) -> Vec<(TokenKind, &str)> {
+        scan(src)
+            .into_iter()
+            .map(|tok| (tok.kind, tok.text))
+            .collect()
+    }
+
+    fn form_texts(src: &str) -> Vec<&str> {
+        let tokens = scan(src);
+        forms(&tokens)
+            .iter()
+            .map(|form| &src[form.start.offset..form.end])
+            .collect()
+    }
+
+    #[test]
+    fn tokens() {
+        use TokenKind::*;
+        assert_eq!(
+            kinds_and_texts("(def 'x -1.5) {x y.z}"),
+            [
+                (Open, "("),
+                (Ident, "def"),
+                (Quote, "'x"),
+                (Number, "-1.5"),
+                (Close, ")"),
+                (Open, "{"),
+                (Ident, "x"),
+                (Ident, "y.z"),
+                (Close, "}"),
+            ]
+        );
+    }
+
+    #[test]
+    fn brackets_in_strings_and_comments() {
+        use TokenKind::*;
+        let src = "(print \"(}{]\" ; not ) a { form\n  \"a \\\" ) b\")";
+        assert_eq!(
+            kinds_and_texts(src),
+            [
+                (Open, "("),
+                (Ident, "print"),
+                (String, "\"(}{]\""),
+                (String, "\"a \\\" ) b\""),
+                (Close, ")"),
+            ]
+        );
+        assert_eq!(form_texts(src), [src]);
+    }
+
+    #[test]
+    fn positions() {
+        let tokens = scan("; comment (\n\t(a\n  \"x\ny\" b)");
+        let pos: Vec<_> = tokens
+            .iter()
+            .map(|tok| (tok.text, tok.pos.line, tok.pos.col))
+            .collect();
+        assert_eq!(
+            pos,
+            [
+                ("(", 2, 2),
+                ("a", 2, 3),
+                ("\"x\ny\"", 3, 3),
+                ("b", 4, 4),
+                (")", 4, 5),
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_forms() {
+        let src = "(def 'a \"}\") ; (\nb\n{(x) ; }\n \"{\"}\n'c";
+        assert_eq!(
+            form_texts(src),
+            ["(def 'a \"}\")", "b", "{(x) ; }\n \"{\"}", "'c"]
+        );
+    }
+
+    #[test]
+    fn unterminated_forms() {
+        assert_eq!(form_texts("(a) (b (c)"), ["(a)", "(b (c)"]);
+        assert_eq!(form_texts("(a \"b)"), ["(a \"b)"]);
+        assert_eq!(form_texts(") a"), [")", "a"]);
+        assert!(form_texts("  ; nothing\n").is_empty());
+    }
+
+    #[test]
+    fn block_elements_of_config() {
+        let src = "(def 'x 1)\n{\n\t(a \"}\")\n\tspace ; {\n\t{b}\n}";
+        let tokens = scan(src);
+        let forms = forms(&tokens);
+        assert!(block_elements(&tokens, &forms[0]).is_empty());
+
+        let elements = block_elements(&tokens, &forms[1]);
+        let texts: Vec<_> = elements
+            .iter()
+            .map(|elem| &src[elem.start.offset..elem.end])
+            .collect();
+        assert_eq!(texts, ["(a \"}\")", "space", "{b}"]);
+        assert_eq!((elements[1].start.line, elements[1].start.col), (4, 2));
+    }
+
+    #[test]
+    fn form_text_keeps_positions() {
+        let src = "(a)\n  (b\n c)";
+        let tokens = scan(src);
+        let forms = forms(&tokens);
+        assert_eq!(form_text(src, &forms[1]), "\n  (b\n c)");
+    }
+
+    #[test]
+    fn default_config() {
+        // Every form is a balanced list or block, and the last one is the prompt
+        let src = crate::DEFAULT_CONFIG;
+        let tokens = scan(src);
+        let forms = forms(&tokens);
+        for form in &forms {
+            let text = &src[form.start.offset..form.end];
+            assert!(text.starts_with('(') || text.starts_with('{'), "{}", text);
+            assert!(text.ends_with(')') || text.ends_with('}'), "{}", text);
+        }
+        assert!(!block_elements(&tokens, forms.last().unwrap()).is_empty());
+    }
+
+    #[test]
+    fn formats_errors() {
+        let src = "(a)\n\t(b  c)\n";
+        assert_eq!(
+            format_error("f.lsp", src, 2, 6, "Error: x"),
+            "f.lsp:2:6: Error: x\n  |\n2 | \t(b  c)\n  | \t    ^"
+        );
+        assert_eq!(format_error("f.lsp", src, 9, 1, "E"), "f.lsp:9:1: E");
+    }
+}
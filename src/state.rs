@@ -26,4 +26,13 @@ impl Shell {
             Shell::Zsh => "%}",
         }
     }
+
+    // Makes arbitrary text safe to put in the prompt.
+    // zsh expands '%' sequences in the output of command substitutions.
+    pub fn escape_text(self, s: &str) -> String {
+        match self {
+            Shell::Zsh => s.replace('%', "%%"),
+            _ => s.to_string(),
+        }
+    }
 }
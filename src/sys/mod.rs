@@ -11,3 +11,8 @@ mod windows;
 pub use windows::*;
 
 pub use whoami::username;
+
+pub struct Process {
+    pub pid: u32,
+    pub name: String,
+}
@@ -1,10 +1,20 @@
-use std::ffi::CStr;
-use std::os::raw::c_char;
+use super::Process;
+use std::ffi::{CStr, CString};
+use std::fs;
+use std::os::raw::{c_char, c_int};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::process;
 use terminal_size;
 use whoami;
 
 extern "C" {
     fn getlogin() -> *const c_char;
+    fn getuid() -> u32;
+    fn geteuid() -> u32;
+    fn getgroups(size: c_int, list: *mut u32) -> c_int;
+    fn access(path: *const c_char, mode: c_int) -> c_int;
 }
 
 pub fn term_size() -> (i32, i32) {
@@ -16,22 +26,326 @@ pub fn term_size() -> (i32, i32) {
     }
 }
 
-pub fn login_name() -> String {
-    let cstr = unsafe { CStr::from_ptr(getlogin()) };
+// getlogin() returns NULL when there's no controlling terminal,
+// like in cron jobs, containers and systemd units
+pub fn login_name() -> Option<String> {
+    let ptr = unsafe { getlogin() };
+    if ptr.is_null() {
+        return None;
+    }
+
+    let cstr = unsafe { CStr::from_ptr(ptr) };
     match cstr.to_str() {
-        Ok(s) => s.to_owned(),
-        Err(_) => whoami::username(),
+        Ok(s) if !s.is_empty() => Some(s.to_owned()),
+        _ => None,
+    }
+}
+
+pub fn uid() -> Option<u32> {
+    Some(unsafe { getuid() })
+}
+
+pub fn euid() -> Option<u32> {
+    Some(unsafe { geteuid() })
+}
+
+pub fn groups() -> Vec<u32> {
+    let count = unsafe { getgroups(0, std::ptr::null_mut()) };
+    if count <= 0 {
+        return Vec::new();
     }
+
+    let mut groups = vec![0u32; count as usize];
+    let count = unsafe { getgroups(count, groups.as_mut_ptr()) };
+    if count < 0 {
+        return Vec::new();
+    }
+
+    groups.truncate(count as usize);
+    groups
+}
+
+// Calls one of the getpwuid_r() family, which take a buffer for the strings
+// they return, growing the buffer until it's big enough. 'lookup' returns
+// the error code and the name, which points into the buffer.
+fn nss_lookup(
+    mut lookup: impl FnMut(*mut c_char, usize) -> (c_int, *const c_char),
+) -> Option<String> {
+    let mut buf: Vec<c_char> = vec![0; 1024];
+    loop {
+        let (ret, name) = lookup(buf.as_mut_ptr(), buf.len());
+        if ret == libc::ERANGE && buf.len() < 1 << 20 {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+
+        if ret != 0 || name.is_null() {
+            return None;
+        }
+
+        let cstr = unsafe { CStr::from_ptr(name) };
+        return match cstr.to_str() {
+            Ok(s) if !s.is_empty() => Some(s.to_owned()),
+            _ => None,
+        };
+    }
+}
+
+// Unlike reading /etc/passwd and /etc/group, these go through NSS,
+// so users and groups from LDAP or SSSD have names too
+pub fn user_name(uid: u32) -> Option<String> {
+    let mut pwd: libc::passwd = unsafe { std::mem::zeroed() };
+    nss_lookup(|buf, len| {
+        let mut result = std::ptr::null_mut();
+        let ret = unsafe { libc::getpwuid_r(uid, &mut pwd, buf, len, &mut result) };
+        if result.is_null() {
+            (ret, std::ptr::null())
+        } else {
+            (ret, pwd.pw_name)
+        }
+    })
+}
+
+pub fn group_name(gid: u32) -> Option<String> {
+    let mut grp: libc::group = unsafe { std::mem::zeroed() };
+    nss_lookup(|buf, len| {
+        let mut result = std::ptr::null_mut();
+        let ret = unsafe { libc::getgrgid_r(gid, &mut grp, buf, len, &mut result) };
+        if result.is_null() {
+            (ret, std::ptr::null())
+        } else {
+            (ret, grp.gr_name)
+        }
+    })
 }
 
 #[cfg(not(target_os = "macos"))]
-pub use whoami::hostname;
+pub fn hostname() -> Option<String> {
+    whoami::fallible::hostname().ok()
+}
 
 #[cfg(target_os = "macos")]
-pub fn hostname() -> String {
-    let un = whoami::hostname();
+pub fn hostname() -> Option<String> {
+    let un = whoami::fallible::hostname().ok()?;
     match un.strip_suffix(".local") {
-        Some(un) => un.to_string(),
-        _ => un,
+        Some(un) => Some(un.to_string()),
+        _ => Some(un),
+    }
+}
+
+// Parses /proc/<pid>/stat, which looks like '1234 (bash) S 1233 ...'.
+// The name may itself contain spaces and parens, so look for the last ')'.
+fn parse_proc_stat(stat: &str) -> Option<(String, u32)> {
+    let start = stat.find('(')?;
+    let end = stat.rfind(')')?;
+    let name = stat.get(start + 1..end)?.to_string();
+    let ppid = stat.get(end + 1..)?.split_whitespace().nth(1)?;
+    Some((name, str::parse::<u32>(ppid).ok()?))
+}
+
+// The /proc functions take the directory to use as /proc,
+// so that they can be tested with a directory of fixtures
+fn read_proc_stat(proc_dir: &Path, pid: u32) -> Option<(String, u32)> {
+    let stat = fs::read_to_string(proc_dir.join(pid.to_string()).join("stat")).ok()?;
+    parse_proc_stat(&stat)
+}
+
+fn ancestors_in(proc_dir: &Path, pid: u32) -> Vec<Process> {
+    let mut procs = Vec::new();
+    let mut pid = match read_proc_stat(proc_dir, pid) {
+        Some((_, ppid)) => ppid,
+        None => return procs,
+    };
+
+    // Limit the depth in case of a loop caused by PID reuse
+    while pid > 1 && procs.len() < 64 {
+        let (name, ppid) = match read_proc_stat(proc_dir, pid) {
+            Some(stat) => stat,
+            None => break,
+        };
+
+        procs.push(Process { pid, name });
+        pid = ppid;
+    }
+
+    procs
+}
+
+// Returns our ancestor processes, starting with the parent.
+// Systems without /proc (like macOS) get an empty list.
+pub fn process_ancestors() -> Vec<Process> {
+    ancestors_in(Path::new("/proc"), process::id())
+}
+
+fn env_var_in(proc_dir: &Path, pid: u32, key: &str) -> Option<String> {
+    let environ = fs::read(proc_dir.join(pid.to_string()).join("environ")).ok()?;
+    environ.split(|ch| *ch == 0).find_map(|var| {
+        let val = var.strip_prefix(key.as_bytes())?.strip_prefix(b"=")?;
+        Some(String::from_utf8_lossy(val).into_owned())
+    })
+}
+
+// Reads an environment variable from another process.
+// This only works for processes owned by the same user, or as root.
+pub fn process_env_var(pid: u32, key: &str) -> Option<String> {
+    env_var_in(Path::new("/proc"), pid, key)
+}
+
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::metadata(a), fs::metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+// Unlike checking the permission bits, access() takes into account
+// read-only mounts, ACLs and our supplementary groups
+pub fn is_writable(path: &Path) -> bool {
+    const W_OK: c_int = 2;
+    let cpath = match CString::new(path.as_os_str().as_bytes()) {
+        Ok(cpath) => cpath,
+        Err(..) => return false,
+    };
+
+    unsafe { access(cpath.as_ptr(), W_OK) == 0 }
+}
+
+pub fn file_owner(path: &Path) -> Option<u32> {
+    fs::metadata(path).ok().map(|meta| meta.uid())
+}
+
+// Mount points in mountinfo escape space, tab, newline and backslash
+// as octal sequences like '\040'
+fn unescape_mountinfo(s: &str) -> PathBuf {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut idx = 0;
+    while idx < bytes.len() {
+        if bytes[idx] == b'\\' && idx + 3 < bytes.len() {
+            let octal = std::str::from_utf8(&bytes[idx + 1..idx + 4]);
+            if let Some(ch) = octal.ok().and_then(|o| u8::from_str_radix(o, 8).ok()) {
+                out.push(ch);
+                idx += 4;
+                continue;
+            }
+        }
+        out.push(bytes[idx]);
+        idx += 1;
+    }
+
+    PathBuf::from(std::ffi::OsStr::from_bytes(&out))
+}
+
+// Finds the type of the file system containing 'dir' by looking for
+// the longest matching mount point in a file like /proc/self/mountinfo.
+// Lines look like:
+//   36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw
+pub fn mount_fs_type(mountinfo: &Path, dir: &Path) -> Option<String> {
+    let content = fs::read_to_string(mountinfo).ok()?;
+    let mut best: Option<(usize, &str)> = None;
+    for line in content.lines() {
+        let (mount, fs) = match line.split_once(" - ") {
+            Some(parts) => parts,
+            None => continue,
+        };
+
+        let mount_point = match mount.split(' ').nth(4) {
+            Some(mp) => unescape_mountinfo(mp),
+            None => continue,
+        };
+
+        let fs_type = match fs.split(' ').next() {
+            Some(fs_type) => fs_type,
+            None => continue,
+        };
+
+        if !dir.starts_with(&mount_point) {
+            continue;
+        }
+
+        // Later mounts on the same mount point hide earlier ones
+        let len = mount_point.components().count();
+        match best {
+            Some((l, _)) if l > len => (),
+            _ => best = Some((len, fs_type)),
+        }
+    }
+
+    best.map(|(_, fs_type)| fs_type.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture;
+
+    #[test]
+    fn user_names() {
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert_eq!(user_name(u32::MAX - 1), None);
+    }
+
+    #[test]
+    fn group_names() {
+        // 'root' on Linux, 'wheel' on the BSDs and macOS
+        assert!(group_name(0).is_some());
+        assert_eq!(group_name(u32::MAX - 1), None);
+    }
+
+    #[test]
+    fn proc_stat() {
+        assert_eq!(
+            parse_proc_stat("1234 (bash) S 1233 1234 1234 34816"),
+            Some(("bash".to_string(), 1233))
+        );
+        assert_eq!(
+            parse_proc_stat("99 (sshd: user (priv)) S 1 99"),
+            Some(("sshd: user (priv)".to_string(), 1))
+        );
+        assert_eq!(parse_proc_stat("99 (bash S"), None);
+        assert_eq!(parse_proc_stat("99 (bash) S x"), None);
+    }
+
+    #[test]
+    fn ancestors() {
+        let dir = fixture(&[
+            ("400/stat", "400 (starstruck) R 300 0"),
+            ("300/stat", "300 (bash) S 200 0"),
+            ("200/stat", "200 (sshd: user@pts/0) S 100 0"),
+            ("100/stat", "100 (sshd) S 1 0"),
+            ("1/stat", "1 (systemd) S 0 0"),
+        ]);
+        let procs: Vec<(u32, String)> = ancestors_in(&dir, 400)
+            .into_iter()
+            .map(|p| (p.pid, p.name))
+            .collect();
+        assert_eq!(
+            procs,
+            [
+                (300, "bash".to_string()),
+                (200, "sshd: user@pts/0".to_string()),
+                (100, "sshd".to_string()),
+            ]
+        );
+
+        // A missing parent ends the walk, and a loop is cut off
+        assert_eq!(ancestors_in(&dir, 500).len(), 0);
+        let dir = fixture(&[("10/stat", "10 (a) S 11 0"), ("11/stat", "11 (b) S 10 0")]);
+        assert_eq!(ancestors_in(&dir, 10).len(), 64);
+    }
+
+    #[test]
+    fn environ() {
+        let dir = fixture(&[(
+            "200/environ",
+            "HOME=/root\0SSH_CONNECTION_X=no\0SSH_CONNECTION=10.0.0.1 5000 10.0.0.2 22\0",
+        )]);
+        assert_eq!(
+            env_var_in(&dir, 200, "SSH_CONNECTION").as_deref(),
+            Some("10.0.0.1 5000 10.0.0.2 22")
+        );
+        assert_eq!(env_var_in(&dir, 200, "SSH_CLIENT"), None);
+        assert_eq!(env_var_in(&dir, 300, "HOME"), None);
     }
 }
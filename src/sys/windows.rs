@@ -1,11 +1,66 @@
+use super::Process;
+use std::fs;
+use std::path::Path;
 use whoami;
 
 pub fn term_size() -> (i32, i32) {
     (80, 60)
 }
 
-pub fn login_name() -> String {
-    whomai::username()
+pub fn login_name() -> Option<String> {
+    Some(whoami::username())
 }
 
-pub use whoami::hostname;
+pub fn uid() -> Option<u32> {
+    None
+}
+
+pub fn euid() -> Option<u32> {
+    None
+}
+
+pub fn groups() -> Vec<u32> {
+    Vec::new()
+}
+
+pub fn user_name(_uid: u32) -> Option<String> {
+    None
+}
+
+pub fn group_name(_gid: u32) -> Option<String> {
+    None
+}
+
+pub fn hostname() -> Option<String> {
+    whoami::fallible::hostname().ok()
+}
+
+pub fn process_ancestors() -> Vec<Process> {
+    Vec::new()
+}
+
+pub fn process_env_var(_pid: u32, _key: &str) -> Option<String> {
+    None
+}
+
+pub fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+pub fn is_writable(path: &Path) -> bool {
+    match fs::metadata(path) {
+        Ok(meta) => !meta.permissions().readonly(),
+        Err(..) => false,
+    }
+}
+
+pub fn file_owner(_path: &Path) -> Option<u32> {
+    None
+}
+
+pub fn mount_fs_type(_mountinfo: &Path, _dir: &Path) -> Option<String> {
+    None
+}
@@ -0,0 +1,252 @@
+use super::profile;
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+// Everything here comes from Linux's /proc and /sys.
+// The roots are kept in the ctx so that they can point somewhere else,
+// like a directory of fixtures. On other systems, the lazies are all none.
+struct SysinfoCtx {
+    proc_dir: PathBuf,
+    sys_dir: PathBuf,
+}
+
+impl SysinfoCtx {
+    fn new() -> Self {
+        Self {
+            proc_dir: PathBuf::from("/proc"),
+            sys_dir: PathBuf::from("/sys"),
+        }
+    }
+}
+
+fn read_trimmed(path: &Path) -> Option<String> {
+    fs::read_to_string(path).ok().map(|s| s.trim().to_string())
+}
+
+fn first_number(path: &Path) -> Option<f64> {
+    let content = fs::read_to_string(path).ok()?;
+    str::parse::<f64>(content.split_whitespace().next()?).ok()
+}
+
+// /proc/loadavg looks like '0.52 0.58 0.59 1/1145 123456'
+fn load_avg(ctx: &SysinfoCtx) -> Option<f64> {
+    first_number(&ctx.proc_dir.join("loadavg"))
+}
+
+// /proc/uptime is '<seconds up> <seconds idle>'
+fn uptime(ctx: &SysinfoCtx) -> Option<f64> {
+    first_number(&ctx.proc_dir.join("uptime"))
+}
+
+// /proc/meminfo has lines like 'MemTotal:       16314660 kB'
+fn mem_used_percent(ctx: &SysinfoCtx) -> Option<f64> {
+    let content = fs::read_to_string(ctx.proc_dir.join("meminfo")).ok()?;
+    let field = |name: &str| {
+        content.lines().find_map(|line| {
+            let rest = line.strip_prefix(name)?.strip_prefix(':')?;
+            str::parse::<f64>(rest.split_whitespace().next()?).ok()
+        })
+    };
+
+    let total = field("MemTotal")?;
+    if total <= 0.0 {
+        return None;
+    }
+
+    // MemAvailable only exists since Linux 3.14
+    let available = match field("MemAvailable") {
+        Some(available) => available,
+        None => {
+            field("MemFree")? + field("Buffers").unwrap_or(0.0) + field("Cached").unwrap_or(0.0)
+        }
+    };
+
+    Some((total - available) / total * 100.0)
+}
+
+// Returns the directories of all batteries in /sys/class/power_supply;
+// AC adapters and USB power supplies live there too
+fn batteries(ctx: &SysinfoCtx) -> Vec<PathBuf> {
+    let entries = match fs::read_dir(ctx.sys_dir.join("class").join("power_supply")) {
+        Ok(entries) => entries,
+        Err(..) => return Vec::new(),
+    };
+
+    let mut batteries: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| read_trimmed(&path.join("type")).as_deref() == Some("Battery"))
+        .collect();
+    batteries.sort();
+    batteries
+}
+
+fn battery_percent(ctx: &SysinfoCtx) -> Option<f64> {
+    let capacities: Vec<f64> = batteries(ctx)
+        .iter()
+        .filter_map(|path| first_number(&path.join("capacity")))
+        .collect();
+    if capacities.is_empty() {
+        return None;
+    }
+
+    Some(capacities.iter().sum::<f64>() / capacities.len() as f64)
+}
+
+fn battery_charging(ctx: &SysinfoCtx) -> Option<bool> {
+    let batteries = batteries(ctx);
+    if batteries.is_empty() {
+        return None;
+    }
+
+    Some(
+        batteries
+            .iter()
+            .any(|path| read_trimmed(&path.join("status")).as_deref() == Some("Charging")),
+    )
+}
+
+fn number_or_none(num: Option<f64>) -> Result<ValRef, StackTrace> {
+    match num {
+        Some(num) => Ok(ValRef::Number(num)),
+        None => Ok(ValRef::None),
+    }
+}
+
+fn bool_or_none(b: Option<bool>) -> Result<ValRef, StackTrace> {
+    match b {
+        Some(b) => Ok(ValRef::Bool(b)),
+        None => Ok(ValRef::None),
+    }
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(SysinfoCtx::new());
+
+    macro_rules! put {
+        ($name: expr, $func: expr, $conv: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap(
+                    $name,
+                    Rc::new(move |_, scope| Ok(($conv($func(&c))?, scope))),
+                ),
+            );
+        };
+    }
+
+    put!("load-avg", load_avg, number_or_none);
+    put!("mem-used-percent", mem_used_percent, number_or_none);
+    put!("uptime", uptime, number_or_none);
+    put!("battery-percent", battery_percent, number_or_none);
+    put!("battery-charging?", battery_charging, bool_or_none);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture;
+
+    fn ctx(dir: &Path) -> SysinfoCtx {
+        SysinfoCtx {
+            proc_dir: dir.join("proc"),
+            sys_dir: dir.join("sys"),
+        }
+    }
+
+    #[test]
+    fn load_and_uptime() {
+        let dir = fixture(&[
+            ("proc/loadavg", "0.52 0.58 0.59 1/1145 123456\n"),
+            ("proc/uptime", "350735.47 234388.90\n"),
+        ]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(load_avg(&ctx), Some(0.52));
+        assert_eq!(uptime(&ctx), Some(350735.47));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn meminfo() {
+        let dir = fixture(&[(
+            "proc/meminfo",
+            "MemTotal:       16000000 kB\n\
+             MemFree:         1000000 kB\n\
+             MemAvailable:    4000000 kB\n\
+             Buffers:          500000 kB\n\
+             Cached:          2000000 kB\n",
+        )]);
+
+        assert_eq!(mem_used_percent(&ctx(&dir)), Some(75.0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn meminfo_without_mem_available() {
+        // Before Linux 3.14; also checks that 'MemFree' doesn't match
+        // a field which only starts with it
+        let dir = fixture(&[(
+            "proc/meminfo",
+            "MemTotal:       16000000 kB\n\
+             MemFreeish:            0 kB\n\
+             MemFree:         2000000 kB\n\
+             Buffers:         1000000 kB\n\
+             Cached:          1000000 kB\n",
+        )]);
+
+        assert_eq!(mem_used_percent(&ctx(&dir)), Some(75.0));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn missing_files() {
+        let dir = fixture(&[("proc/meminfo", "MemTotal: 0 kB\n")]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(load_avg(&ctx), None);
+        assert_eq!(uptime(&ctx), None);
+        assert_eq!(mem_used_percent(&ctx), None);
+        assert_eq!(battery_percent(&ctx), None);
+        assert_eq!(battery_charging(&ctx), None);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn battery_next_to_ac_adapter() {
+        let dir = fixture(&[
+            ("sys/class/power_supply/AC/type", "Mains\n"),
+            ("sys/class/power_supply/AC/online", "1\n"),
+            ("sys/class/power_supply/BAT0/type", "Battery\n"),
+            ("sys/class/power_supply/BAT0/capacity", "87\n"),
+            ("sys/class/power_supply/BAT0/status", "Charging\n"),
+        ]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(batteries(&ctx), [dir.join("sys/class/power_supply/BAT0")]);
+        assert_eq!(battery_percent(&ctx), Some(87.0));
+        assert_eq!(battery_charging(&ctx), Some(true));
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn multiple_batteries() {
+        let dir = fixture(&[
+            ("sys/class/power_supply/BAT0/type", "Battery\n"),
+            ("sys/class/power_supply/BAT0/capacity", "100\n"),
+            ("sys/class/power_supply/BAT0/status", "Full\n"),
+            ("sys/class/power_supply/BAT1/type", "Battery\n"),
+            ("sys/class/power_supply/BAT1/capacity", "50\n"),
+            ("sys/class/power_supply/BAT1/status", "Discharging\n"),
+        ]);
+        let ctx = ctx(&dir);
+
+        assert_eq!(battery_percent(&ctx), Some(75.0));
+        assert_eq!(battery_charging(&ctx), Some(false));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,296 @@
+use super::prefetch;
+use osyris::bstring::BString;
+use osyris::eval::{self, FuncResult, Scope, StackTrace, ValRef};
+use std::cell::Cell;
+use std::ffi::OsString;
+use std::io::Read;
+use std::process::{Child, Command, Stdio};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The time by which the prompt has to be done. Commands are killed
+// when it passes, and threads started by run and Pending are abandoned.
+// (timeout ...) sets an earlier deadline for part of the prompt.
+thread_local! {
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+pub fn set_deadline(deadline: Option<Instant>) {
+    DEADLINE.with(|d| d.set(deadline));
+}
+
+fn deadline() -> Option<Instant> {
+    DEADLINE.with(|d| d.get())
+}
+
+fn expired() -> bool {
+    match deadline() {
+        Some(deadline) => Instant::now() >= deadline,
+        None => false,
+    }
+}
+
+fn recv<T>(rx: &mpsc::Receiver<T>) -> Option<T> {
+    match deadline() {
+        Some(deadline) => rx
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))
+            .ok(),
+        None => rx.recv().ok(),
+    }
+}
+
+// A value which is being computed on another thread.
+//
+// Lazies read files under the cwd, the home directory or /etc through
+// Pending or run, because any of those might be on a network mount,
+// and reading from a dead network mount hangs forever. The thread
+// doing the reading is abandoned at the deadline, so the prompt isn't.
+// Files which are always local, like those in /proc, are read directly.
+pub struct Pending<T> {
+    rx: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> Pending<T> {
+    pub fn spawn(f: impl FnOnce() -> T + Send + 'static) -> Self {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let _ = tx.send(f());
+        });
+        Self { rx }
+    }
+
+    // Returns None if the value isn't done before the deadline.
+    // The thread is left to finish on its own.
+    pub fn wait(self) -> Option<T> {
+        recv(&self.rx)
+    }
+}
+
+// Runs 'f', and returns None if it doesn't finish before the deadline
+pub fn run<T: Send + 'static>(f: impl FnOnce() -> T + Send + 'static) -> Option<T> {
+    if deadline().is_none() {
+        return Some(f());
+    }
+
+    Pending::spawn(f).wait()
+}
+
+pub fn argv(name: &str, args: &[ValRef]) -> Result<Vec<OsString>, StackTrace> {
+    let mut argv = Vec::new();
+    for arg in args {
+        match arg {
+            ValRef::String(s) => argv.push(s.to_os_str()),
+            _ => {
+                return Err(StackTrace::from_string(format!(
+                    "'{}' requires string arguments",
+                    name
+                )))
+            }
+        }
+    }
+
+    if argv.is_empty() {
+        return Err(StackTrace::from_string(format!(
+            "'{}' requires at least 1 argument",
+            name
+        )));
+    }
+
+    Ok(argv)
+}
+
+// A program which has been started, with its output being read
+pub struct Running {
+    child: Child,
+    output: Pending<Vec<u8>>,
+}
+
+// Starts a program, or returns None if the deadline has already passed
+pub fn start(argv: &[OsString]) -> Result<Option<Running>, StackTrace> {
+    if expired() {
+        return Ok(None);
+    }
+
+    let mut child = match Command::new(&argv[0])
+        .args(&argv[1..])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            return Err(StackTrace::from_string(format!(
+                "'exec' {:?}: {}",
+                argv[0], err
+            )))
+        }
+    };
+
+    let mut stdout = child.stdout.take().unwrap();
+    let output = Pending::spawn(move || {
+        let mut output = Vec::new();
+        let _ = stdout.read_to_end(&mut output);
+        output
+    });
+
+    Ok(Some(Running { child, output }))
+}
+
+// Kills a program whose output isn't needed
+pub fn kill(running: Running) {
+    let mut child = running.child;
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// Waits for a program to exit, and returns its output.
+// The program is killed if it's still running at the deadline,
+// and its output is thrown away, since it might be cut off.
+fn finish(running: Running) -> Vec<u8> {
+    let Running { mut child, output } = running;
+
+    // The output ends when the program exits or closes its stdout;
+    // in the second case, it still gets until the deadline to exit
+    let output = output.wait();
+    loop {
+        match child.try_wait() {
+            Ok(Some(..)) | Err(..) => break,
+            Ok(None) if output.is_none() || expired() => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Vec::new();
+            }
+            Ok(None) => thread::sleep(Duration::from_millis(1)),
+        }
+    }
+
+    output.unwrap_or_default()
+}
+
+// (exec program args...)
+// Runs a program and returns its output as a string.
+// Past the deadline, the program is killed (or not started at all),
+// and the output is an empty string, so that the rest of the prompt
+// still shows up. Programs started early with 'prefetch-exec'
+// are picked up here.
+fn exec(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    let argv = argv("exec", &args)?;
+    let running = match prefetch::take_exec(&argv) {
+        Some(running) => Some(running),
+        None => start(&argv)?,
+    };
+
+    let output = running.map(finish).unwrap_or_default();
+    Ok((ValRef::String(Rc::new(BString::from_bytes(&output))), scope))
+}
+
+// 'exec' used to return a port, which had to be read with 'read'.
+// Strings are passed through, so that '(read (exec ...))' keeps working.
+fn wrap_read(read: ValRef) -> Rc<dyn Fn(Vec<ValRef>, Scope) -> FuncResult> {
+    Rc::new(move |args, scope| match args.first() {
+        Some(ValRef::String(s)) if args.len() == 1 => Ok((ValRef::String(s.clone()), scope)),
+        _ => eval::call(&read, args, scope),
+    })
+}
+
+// Blocks are evaluated, anything else is the value itself
+fn evaluate(val: &ValRef, mut scope: Scope) -> FuncResult {
+    match val {
+        ValRef::Block(exprs) => {
+            let mut retval = ValRef::None;
+            for expr in exprs.iter() {
+                (retval, scope) = eval::eval(expr, scope)?;
+            }
+            Ok((retval, scope))
+        }
+        ValRef::Func(..) => eval::call(val, vec![], scope),
+        val => Ok((val.clone(), scope)),
+    }
+}
+
+// (timeout ms expr fallback)
+// Evaluates expr, which is usually a block, with a deadline 'ms' from now.
+// If the deadline passes before expr is done, fallback is evaluated instead.
+fn timeout(args: Vec<ValRef>, scope: Scope) -> FuncResult {
+    if args.len() != 3 {
+        return Err(StackTrace::from_str("'timeout' requires 3 arguments"));
+    }
+
+    let ms = match &args[0] {
+        ValRef::Number(ms) if *ms >= 0.0 => *ms,
+        _ => {
+            return Err(StackTrace::from_str(
+                "'timeout' requires a non-negative number as its first argument",
+            ))
+        }
+    };
+
+    // A deadline too far away to represent is no deadline at all
+    let outer = deadline();
+    let inner = Duration::try_from_secs_f64(ms / 1000.0)
+        .ok()
+        .and_then(|d| Instant::now().checked_add(d));
+    set_deadline(match (outer, inner) {
+        (Some(outer), Some(inner)) => Some(outer.min(inner)),
+        (outer, inner) => outer.or(inner),
+    });
+
+    let res = evaluate(&args[1], scope.clone());
+    let timed_out = expired();
+    set_deadline(outer);
+
+    // Lazies give up and return none when they time out,
+    // so the result can't be trusted even if there was no error
+    if timed_out {
+        return evaluate(&args[2], scope);
+    }
+
+    res
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    scope = scope.put_func("exec", Rc::new(exec));
+    if let Ok(read) = super::lookup("read", &scope) {
+        scope = scope.put_func("read", wrap_read(read));
+    }
+    scope = scope.put_func("timeout", Rc::new(timeout));
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: &[&str]) -> Vec<ValRef> {
+        args.iter()
+            .map(|s| ValRef::String(Rc::new(BString::from_str(s))))
+            .collect()
+    }
+
+    fn exec_output(args: &[&str]) -> Vec<u8> {
+        match exec(strings(args), Scope::new()) {
+            Ok((ValRef::String(s), _)) => s.as_bytes().to_vec(),
+            _ => panic!("'exec' didn't return a string"),
+        }
+    }
+
+    #[test]
+    fn exec_returns_output() {
+        set_deadline(None);
+        assert_eq!(exec_output(&["echo", "hello"]), b"hello\n");
+    }
+
+    #[test]
+    fn exec_returns_empty_string_at_deadline() {
+        set_deadline(Instant::now().checked_add(Duration::from_millis(50)));
+        let start = Instant::now();
+        assert_eq!(exec_output(&["sleep", "30"]), b"");
+        assert!(start.elapsed() < Duration::from_secs(10));
+
+        // Past the deadline, programs aren't started at all
+        assert_eq!(exec_output(&["echo", "hello"]), b"");
+        set_deadline(None);
+    }
+}
@@ -0,0 +1,337 @@
+use super::cwd;
+use super::prefetch;
+use super::profile;
+use super::timeout;
+use super::util::{self, unquote};
+use osyris::bstring::BString;
+use osyris::eval::{Scope, StackTrace, ValRef};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+#[derive(Copy, Clone, PartialEq, Eq, Hash)]
+enum Tool {
+    Rust,
+    Node,
+    Go,
+    Python,
+}
+
+impl Tool {
+    // The name used for the tool in asdf's .tool-versions
+    fn asdf_name(self) -> &'static str {
+        match self {
+            Tool::Rust => "rust",
+            Tool::Node => "nodejs",
+            Tool::Go => "golang",
+            Tool::Python => "python",
+        }
+    }
+}
+
+struct ToolchainCtx {
+    has_searched_dirs: bool,
+    dirs: Vec<PathBuf>,
+    versions: HashMap<Tool, Option<String>>,
+
+    // Set by (prefetch 'toolchain) and friends
+    pending: HashMap<Tool, timeout::Pending<Option<String>>>,
+}
+
+// Version files like .nvmrc contain just the version
+fn read_version_file(path: &Path) -> Option<String> {
+    let line = util::read_first_line(path)?;
+    let line = String::from_utf8_lossy(&line);
+    let line = line.trim();
+    if line.is_empty() {
+        None
+    } else {
+        Some(line.to_string())
+    }
+}
+
+// rust-toolchain.toml looks like:
+//   [toolchain]
+//   channel = "1.70.0"
+fn read_rust_toolchain_toml(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut in_toolchain = false;
+    for line in content.lines() {
+        let line = line.trim();
+        if line.starts_with('[') {
+            in_toolchain = line == "[toolchain]";
+            continue;
+        }
+
+        if !in_toolchain {
+            continue;
+        }
+
+        if let Some((key, val)) = line.split_once('=') {
+            if key.trim() == "channel" {
+                return Some(unquote(val).to_string());
+            }
+        }
+    }
+
+    None
+}
+
+// go.mod contains a 'go 1.21' directive, and optionally a more precise
+// 'toolchain go1.21.3' directive which takes precedence
+fn read_go_mod(path: &Path) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    let mut version = None;
+    for line in content.lines() {
+        let mut parts = line.split_whitespace();
+        match (parts.next(), parts.next()) {
+            (Some("toolchain"), Some(tc)) => {
+                return Some(tc.strip_prefix("go").unwrap_or(tc).to_string());
+            }
+            (Some("go"), Some(v)) => version = Some(v.to_string()),
+            _ => (),
+        }
+    }
+
+    version
+}
+
+// .tool-versions contains lines like 'nodejs 18.16.0'
+fn read_tool_versions(path: &Path, tool: Tool) -> Option<String> {
+    let content = fs::read_to_string(path).ok()?;
+    for line in content.lines() {
+        let line = match line.split_once('#') {
+            Some((line, _)) => line,
+            None => line,
+        };
+
+        let mut parts = line.split_whitespace();
+        if parts.next() == Some(tool.asdf_name()) {
+            return parts.next().map(|v| v.to_string());
+        }
+    }
+
+    None
+}
+
+fn find_in_dir(dir: &Path, tool: Tool) -> Option<String> {
+    let version = match tool {
+        // The legacy rust-toolchain file is either TOML or a bare channel name
+        Tool::Rust => read_rust_toolchain_toml(&dir.join("rust-toolchain.toml"))
+            .or_else(|| read_rust_toolchain_toml(&dir.join("rust-toolchain")))
+            .or_else(|| {
+                read_version_file(&dir.join("rust-toolchain")).filter(|l| !l.starts_with('['))
+            }),
+        Tool::Node => read_version_file(&dir.join(".nvmrc"))
+            .or_else(|| read_version_file(&dir.join(".node-version"))),
+        Tool::Go => read_go_mod(&dir.join("go.mod")),
+        Tool::Python => read_version_file(&dir.join(".python-version")),
+    };
+
+    version.or_else(|| read_tool_versions(&dir.join(".tool-versions"), tool))
+}
+
+fn find_version(dirs: &[PathBuf], tool: Tool) -> Option<String> {
+    dirs.iter().find_map(|dir| find_in_dir(dir, tool))
+}
+
+impl ToolchainCtx {
+    fn new() -> Self {
+        Self {
+            has_searched_dirs: false,
+            dirs: Vec::new(),
+            versions: HashMap::new(),
+            pending: HashMap::new(),
+        }
+    }
+
+    // Like git::search_gitdir, walk from the cwd towards the root;
+    // the nearest directory which pins a version wins
+    fn find_dirs(&mut self) {
+        if self.has_searched_dirs {
+            return;
+        }
+
+        self.has_searched_dirs = true;
+        for cwd in cwd::search_starts() {
+            self.dirs.extend(cwd.ancestors().map(|p| p.to_path_buf()));
+        }
+    }
+
+    fn prefetch(&mut self, tool: Tool) {
+        if self.versions.contains_key(&tool) || self.pending.contains_key(&tool) {
+            return;
+        }
+
+        self.find_dirs();
+        let dirs = self.dirs.clone();
+        let pending = timeout::Pending::spawn(move || find_version(&dirs, tool));
+        self.pending.insert(tool, pending);
+    }
+
+    fn version(&mut self, tool: Tool) -> Option<String> {
+        if let Some(version) = self.versions.get(&tool) {
+            return version.clone();
+        }
+
+        let version = match self.pending.remove(&tool) {
+            Some(pending) => pending.wait().flatten(),
+            None => {
+                self.find_dirs();
+                let dirs = self.dirs.clone();
+                timeout::run(move || find_version(&dirs, tool)).flatten()
+            }
+        };
+        self.versions.insert(tool, version.clone());
+        version
+    }
+}
+
+fn version(ctx: &Rc<RefCell<ToolchainCtx>>, tool: Tool) -> Result<ValRef, StackTrace> {
+    match ctx.borrow_mut().version(tool) {
+        Some(version) => Ok(ValRef::String(Rc::new(BString::from_string(version)))),
+        None => Ok(ValRef::None),
+    }
+}
+
+pub fn init(mut scope: Scope) -> Scope {
+    let ctx = Rc::new(RefCell::new(ToolchainCtx::new()));
+
+    macro_rules! put {
+        ($name: expr, $tool: expr) => {
+            let c = ctx.clone();
+            scope = scope.put_lazy(
+                $name,
+                profile::wrap(
+                    $name,
+                    Rc::new(move |_, scope| Ok((version(&c, $tool)?, scope))),
+                ),
+            );
+
+            let c = ctx.clone();
+            prefetch::register($name, Rc::new(move || c.borrow_mut().prefetch($tool)));
+            let c = ctx.clone();
+            prefetch::register("toolchain", Rc::new(move || c.borrow_mut().prefetch($tool)));
+        };
+    }
+
+    put!("toolchain-rust", Tool::Rust);
+    put!("toolchain-node", Tool::Node);
+    put!("toolchain-go", Tool::Go);
+    put!("toolchain-python", Tool::Python);
+    scope
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::fixture;
+
+    fn find(files: &[(&str, &str)], tool: Tool) -> Option<String> {
+        find_in_dir(&fixture(files), tool)
+    }
+
+    #[test]
+    fn rust() {
+        let toml = "[other]\nchannel = \"beta\"\n[toolchain]\nchannel = \"1.70.0\"\n";
+        assert_eq!(
+            find(&[("rust-toolchain.toml", toml)], Tool::Rust).as_deref(),
+            Some("1.70.0")
+        );
+        assert_eq!(
+            find(&[("rust-toolchain", toml)], Tool::Rust).as_deref(),
+            Some("1.70.0")
+        );
+        assert_eq!(
+            find(&[("rust-toolchain", "nightly\n")], Tool::Rust).as_deref(),
+            Some("nightly")
+        );
+        assert_eq!(
+            find(&[("rust-toolchain", "[toolchain]\n")], Tool::Rust),
+            None
+        );
+        assert_eq!(
+            find(
+                &[("rust-toolchain.toml", toml), ("rust-toolchain", "nightly")],
+                Tool::Rust
+            )
+            .as_deref(),
+            Some("1.70.0")
+        );
+    }
+
+    #[test]
+    fn version_files() {
+        assert_eq!(
+            find(&[(".nvmrc", "  v18.16.0\n")], Tool::Node).as_deref(),
+            Some("v18.16.0")
+        );
+        assert_eq!(
+            find(&[(".node-version", "20")], Tool::Node).as_deref(),
+            Some("20")
+        );
+        assert_eq!(
+            find(&[(".nvmrc", "18"), (".node-version", "20")], Tool::Node).as_deref(),
+            Some("18")
+        );
+        assert_eq!(find(&[(".nvmrc", "\n")], Tool::Node), None);
+        assert_eq!(
+            find(&[(".python-version", "3.11.4\n")], Tool::Python).as_deref(),
+            Some("3.11.4")
+        );
+        assert_eq!(find(&[(".python-version", "3.11.4")], Tool::Node), None);
+    }
+
+    #[test]
+    fn go_mod() {
+        let gomod = "module example.com/foo\n\ngo 1.21\n";
+        assert_eq!(
+            find(&[("go.mod", gomod)], Tool::Go).as_deref(),
+            Some("1.21")
+        );
+        let gomod = "module example.com/foo\n\ngo 1.21\ntoolchain go1.21.3\n";
+        assert_eq!(
+            find(&[("go.mod", gomod)], Tool::Go).as_deref(),
+            Some("1.21.3")
+        );
+        assert_eq!(
+            find(&[("go.mod", "module example.com/foo\n")], Tool::Go),
+            None
+        );
+    }
+
+    #[test]
+    fn tool_versions() {
+        let versions =
+            "nodejs 18.16.0 # LTS\n# golang 1.20\ngolang 1.21.0\npython 3.11.4 3.10.12\n";
+        let files = [(".tool-versions", versions)];
+        assert_eq!(find(&files, Tool::Node).as_deref(), Some("18.16.0"));
+        assert_eq!(find(&files, Tool::Go).as_deref(), Some("1.21.0"));
+        assert_eq!(find(&files, Tool::Python).as_deref(), Some("3.11.4"));
+        assert_eq!(find(&files, Tool::Rust), None);
+
+        // A tool's own version file wins over .tool-versions in the same directory
+        let files = [(".tool-versions", versions), (".nvmrc", "20")];
+        assert_eq!(find(&files, Tool::Node).as_deref(), Some("20"));
+    }
+
+    #[test]
+    fn nearest_dir_wins() {
+        let dir = fixture(&[
+            (".nvmrc", "18"),
+            (".python-version", "3.11"),
+            ("a/.tool-versions", "nodejs 20"),
+            ("a/b/c/.keep", ""),
+        ]);
+        let dirs: Vec<PathBuf> = dir
+            .join("a/b/c")
+            .ancestors()
+            .map(|p| p.to_path_buf())
+            .collect();
+        assert_eq!(find_version(&dirs, Tool::Node).as_deref(), Some("20"));
+        assert_eq!(find_version(&dirs, Tool::Python).as_deref(), Some("3.11"));
+        assert_eq!(find_version(&dirs[..2], Tool::Python), None);
+    }
+}
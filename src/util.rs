@@ -0,0 +1,109 @@
+use osyris::bstring::BString;
+use osyris::eval::ValRef;
+use std::env;
+use std::ffi::OsString;
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+use std::rc::Rc;
+
+// Returns None for variables which are unset or empty,
+// so that configs can use the lazies as conditions
+pub fn non_empty_var_os(key: &str) -> Option<OsString> {
+    match env::var_os(key) {
+        Some(val) if !val.is_empty() => Some(val),
+        _ => None,
+    }
+}
+
+pub fn is_var_set(key: &str) -> bool {
+    non_empty_var_os(key).is_some()
+}
+
+pub fn non_empty_var(key: &str) -> Option<String> {
+    match env::var(key) {
+        Ok(val) if !val.is_empty() => Some(val),
+        _ => None,
+    }
+}
+
+pub fn string_or_none(s: Option<&str>) -> ValRef {
+    match s {
+        Some(s) => ValRef::String(Rc::new(BString::from_str(s))),
+        None => ValRef::None,
+    }
+}
+
+// Names like 'git are strings, but accept quotes too,
+// in case they're evaluated to a quote rather than a string
+pub fn as_name(val: &ValRef) -> Option<&Rc<BString>> {
+    match val {
+        ValRef::String(s) | ValRef::Quote(s) => Some(s),
+        _ => None,
+    }
+}
+
+// Strips one pair of matching quotes, as used by YAML and TOML
+pub fn unquote(s: &str) -> &str {
+    let s = s.trim();
+    for q in ['"', '\''] {
+        if let Some(s) = s.strip_prefix(q).and_then(|s| s.strip_suffix(q)) {
+            return s;
+        }
+    }
+    s
+}
+
+// Reads only the first line, without the newline,
+// so that a huge file doesn't get read into memory
+pub fn read_first_line(path: &Path) -> Option<Vec<u8>> {
+    let f = fs::File::open(path).ok()?;
+    BufReader::new(f).split(b'\n').next()?.ok()
+}
+
+// FNV-1a, for anything which has to hash the same way across Rust versions
+pub fn fnv1a_32(bytes: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811c9dc5;
+    for b in bytes {
+        hash ^= *b as u32;
+        hash = hash.wrapping_mul(0x01000193);
+    }
+    hash
+}
+
+pub fn fnv1a_64(bytes: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in bytes {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+// Tests which set environment variables hold this,
+// since tests run in parallel and the environment is shared
+#[cfg(test)]
+pub static ENV_LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+
+// Creates a new temporary directory containing the given files
+#[cfg(test)]
+pub fn fixture(files: &[(&str, &str)]) -> std::path::PathBuf {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+    let mut dir = env::temp_dir();
+    dir.push(format!(
+        "starstruck-test-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = fs::remove_dir_all(&dir);
+
+    for (name, content) in files {
+        let path = dir.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
@@ -1,3 +1,11 @@
+; Start the slow parts early, so that they run at the same time
+; rather than one after the other. The toolchain is looked up on another
+; thread while has-git? waits for the repo, and 'git diff' runs while
+; the user, host and directory parts below are being built.
+(prefetch 'toolchain-rust 'git)
+(if has-git?
+	{(prefetch-exec "git" "-C" git-workdir "diff" "--name-only")})
+
 {
 	; Username and host part
 	(if is-remote?
@@ -22,6 +30,15 @@
 				{(bold-yellow "*")})
 			space)})
 
+	; Rust version pinned by the project
+	(if toolchain-rust
+		{(list (magenta "rust " toolchain-rust) space)})
+
+	; Segment from the project's .starstruck.lsp, once trusted with --allow
+	(if project-untrusted?
+		{(list (red "[untrusted]") space)}
+		{(if project-segment {(list project-segment space)})})
+
 	; Newline if there's not much space left on the line
 	(if [column > [term-width - 40]] {"\n"})
 